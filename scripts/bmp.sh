@@ -1,3 +1,3 @@
-ARGS="--width 500 --height 300 --rgb-discrete"
+ARGS="--width 500 --height 300 --colour discrete"
 
 ./target/release/sable ${ARGS} bmp -i 4000 -o doc/img.bmp
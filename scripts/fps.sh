@@ -0,0 +1,11 @@
+# Usage: ./scripts/fps.sh [path to sable binary]
+# Reports frames per second for a 2000x2000 grid, including the BMP export
+BIN=${1:-./target/release/sable}
+FRAMES=300
+
+for THREADS in 1 2 4 8; do
+    START=$(date +%s%N)
+    ${BIN} --threads ${THREADS} --width 2000 --height 2000 bmp -i ${FRAMES} -o /tmp/sable_fps.bmp
+    END=$(date +%s%N)
+    echo "${THREADS} threads: $((FRAMES * 1000000000 / (END - START))) fps"
+done
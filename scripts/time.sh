@@ -1,4 +1,8 @@
-ARGS="--width 2000 --height 2000 terminal -i 500"
+# Usage: [WIDTH=...] [HEIGHT=...] ./scripts/time.sh [ribbons|margolus]
+SCHEDULER=${1:-ribbons}
+WIDTH=${WIDTH:-2000}
+HEIGHT=${HEIGHT:-2000}
+ARGS="--scheduler ${SCHEDULER} --width ${WIDTH} --height ${HEIGHT} terminal -i 500"
 
 echo "1 thread"
 time ./target/release/sable --threads 1 ${ARGS} > /dev/null
@@ -0,0 +1,359 @@
+use crate::colour::{self, ColourMap};
+use crate::grid::{Boundaries, Boundary, Grid, Scheduler, DEFAULT_DISPERSION};
+use crate::material::Cell;
+use crate::render::RenderMode;
+use crate::shape::Shape;
+use crate::spawner::Spawner;
+use std::sync::Arc;
+
+/// Reasons a [`GridBuilder`] can refuse to build a grid
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    ZeroWidth,
+    ZeroHeight,
+    ZeroThreads,
+    /// The number of cells doesn't fit in memory
+    TooLarge {
+        width: usize,
+        height: usize,
+    },
+    /// None of the points of a spawner's shape are inside the grid
+    SpawnerOutside(Shape),
+    /// A spawner would drop more cells per frame than its shape covers
+    SpawnRate {
+        rate: usize,
+        n_cells: usize,
+    },
+    /// Only one of the left and right edges is periodic
+    UnpairedPeriodic,
+    /// The bottom edge can't wrap around
+    PeriodicBottom,
+    /// The Margolus scheduler needs an even width to wrap around
+    PeriodicOddWidth(usize),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::ZeroWidth => write!(fmt, "Width must be at least 1"),
+            BuildError::ZeroHeight => write!(fmt, "Height must be at least 1"),
+            BuildError::ZeroThreads => {
+                write!(fmt, "Thread count must be at least 1")
+            }
+            BuildError::TooLarge { width, height } => {
+                write!(fmt, "A {}x{} grid is too large", width, height)
+            }
+            BuildError::SpawnerOutside(shape) => {
+                write!(fmt, "Spawner {} is outside of the grid", shape)
+            }
+            BuildError::SpawnRate { rate, n_cells } => write!(
+                fmt,
+                "Spawn rate {} is larger than the {} cells the spawner covers",
+                rate, n_cells
+            ),
+            BuildError::UnpairedPeriodic => write!(
+                fmt,
+                "The left and right edges must either both be periodic or \
+                 neither be"
+            ),
+            BuildError::PeriodicBottom => {
+                write!(fmt, "The bottom edge can't be periodic")
+            }
+            BuildError::PeriodicOddWidth(width) => write!(
+                fmt,
+                "Periodic edges need an even width with the Margolus \
+                 scheduler, got {}",
+                width
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Configures and validates a [`Grid`] before creating it
+///
+/// ```
+/// use sable::{GridBuilder, Scheduler};
+///
+/// let grid = GridBuilder::new(64, 48)
+///     .threads(2)
+///     .seed(7)
+///     .scheduler(Scheduler::Margolus)
+///     .build()
+///     .unwrap();
+/// assert_eq!(grid.get_dims(), (64, 48));
+/// assert!(GridBuilder::new(0, 48).build().is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct GridBuilder {
+    width: usize,
+    height: usize,
+    n_threads: usize,
+    seed: u64,
+    colour_map: Arc<dyn ColourMap>,
+    render_mode: RenderMode,
+    scheduler: Scheduler,
+    dispersion: usize,
+    boundaries: Boundaries,
+    spawn_rate: Option<usize>,
+    spawners: Vec<Spawner>,
+}
+
+impl GridBuilder {
+    /// Starts from a single thread, seed 0, the continuous colour map and
+    /// the default scheduler and dispersion
+    pub fn new(width: usize, height: usize) -> GridBuilder {
+        GridBuilder {
+            width,
+            height,
+            n_threads: 1,
+            seed: 0,
+            colour_map: Arc::new(colour::hsv_to_rgb),
+            render_mode: RenderMode::default(),
+            scheduler: Scheduler::default(),
+            dispersion: DEFAULT_DISPERSION,
+            boundaries: Boundaries::default(),
+            spawn_rate: None,
+            spawners: vec![],
+        }
+    }
+
+    pub fn threads(mut self, n_threads: usize) -> GridBuilder {
+        self.n_threads = n_threads;
+        self
+    }
+
+    /// The same seed gives the same result for any number of threads
+    pub fn seed(mut self, seed: u64) -> GridBuilder {
+        self.seed = seed;
+        self
+    }
+
+    /// Maps the colour value of a cell to the RGBA colour it is rendered with
+    pub fn colour_map(mut self, colour_map: Arc<dyn ColourMap>) -> GridBuilder {
+        self.colour_map = colour_map;
+        self
+    }
+
+    /// What the colours of rendered cells are derived from, the colour
+    /// value of each cell by default
+    pub fn render_mode(mut self, mode: RenderMode) -> GridBuilder {
+        self.render_mode = mode;
+        self
+    }
+
+    pub fn scheduler(mut self, scheduler: Scheduler) -> GridBuilder {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// Number of cells a liquid can flow sideways in a single frame
+    pub fn dispersion(mut self, dispersion: usize) -> GridBuilder {
+        self.dispersion = dispersion;
+        self
+    }
+
+    /// Every edge is solid by default
+    pub fn boundaries(mut self, boundaries: Boundaries) -> GridBuilder {
+        self.boundaries = boundaries;
+        self
+    }
+
+    /// Number of grains the default spawner tries to drop along the top row
+    /// per frame. Defaults to one for every 20 columns
+    pub fn spawn_rate(mut self, rate: usize) -> GridBuilder {
+        self.spawn_rate = Some(rate);
+        self
+    }
+
+    /// Adds a spawner, replacing the default one along the top row
+    pub fn spawner(mut self, spawner: Spawner) -> GridBuilder {
+        self.spawners.push(spawner);
+        self
+    }
+
+    fn get_spawners(&self) -> Vec<Spawner> {
+        if !self.spawners.is_empty() {
+            return self.spawners.clone();
+        }
+        let spawner = Spawner::top_row(self.width);
+        match self.spawn_rate {
+            Some(rate) => vec![spawner.rate(rate)],
+            None => vec![spawner],
+        }
+    }
+
+    fn validate(&self) -> Result<(), BuildError> {
+        let (width, height) = (self.width, self.height);
+        if width == 0 {
+            return Err(BuildError::ZeroWidth);
+        }
+        if height == 0 {
+            return Err(BuildError::ZeroHeight);
+        }
+        if self.n_threads == 0 {
+            return Err(BuildError::ZeroThreads);
+        }
+        let too_large = width
+            .checked_mul(height)
+            .and_then(|size| size.checked_mul(std::mem::size_of::<Cell>()))
+            .is_none_or(|bytes| bytes > isize::MAX as usize);
+        if too_large {
+            return Err(BuildError::TooLarge { width, height });
+        }
+        let Boundaries {
+            left,
+            right,
+            bottom,
+        } = self.boundaries;
+        if (left == Boundary::Periodic) != (right == Boundary::Periodic) {
+            return Err(BuildError::UnpairedPeriodic);
+        }
+        if bottom == Boundary::Periodic {
+            return Err(BuildError::PeriodicBottom);
+        }
+        let margolus = self.scheduler == Scheduler::Margolus;
+        if margolus && self.boundaries.is_periodic() && width % 2 == 1 {
+            return Err(BuildError::PeriodicOddWidth(width));
+        }
+        for spawner in self.get_spawners() {
+            let n_cells = spawner.shape.points_within((width, height)).len();
+            if n_cells == 0 {
+                return Err(BuildError::SpawnerOutside(spawner.shape));
+            }
+            if spawner.rate > n_cells {
+                let rate = spawner.rate;
+                return Err(BuildError::SpawnRate { rate, n_cells });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build(&self) -> Result<Grid, BuildError> {
+        self.validate()?;
+        let mut grid = Grid::new(
+            self.width,
+            self.height,
+            self.n_threads,
+            self.seed,
+            Arc::clone(&self.colour_map),
+        );
+        grid.set_boundaries(self.boundaries);
+        grid.set_scheduler(self.scheduler);
+        grid.set_render_mode(self.render_mode);
+        grid.set_dispersion(self.dispersion);
+        grid.set_spawners(self.get_spawners());
+        Ok(grid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let err = |b: GridBuilder| b.build().err();
+        assert_eq!(err(GridBuilder::new(0, 5)), Some(BuildError::ZeroWidth));
+        assert_eq!(err(GridBuilder::new(5, 0)), Some(BuildError::ZeroHeight));
+        assert_eq!(
+            err(GridBuilder::new(5, 5).threads(0)),
+            Some(BuildError::ZeroThreads)
+        );
+        assert_eq!(
+            err(GridBuilder::new(usize::MAX / 2, 3)),
+            Some(BuildError::TooLarge {
+                width: usize::MAX / 2,
+                height: 3
+            })
+        );
+        assert_eq!(
+            err(GridBuilder::new(5, 5).spawn_rate(6)),
+            Some(BuildError::SpawnRate {
+                rate: 6,
+                n_cells: 5
+            })
+        );
+        let outside = Shape::Point { x: 5, y: 0 };
+        assert_eq!(
+            err(GridBuilder::new(5, 5).spawner(Spawner::new(outside))),
+            Some(BuildError::SpawnerOutside(outside))
+        );
+        let corner = Shape::Circle { x: 0, y: 0, r: 1 };
+        assert_eq!(
+            err(GridBuilder::new(5, 5).spawner(Spawner::new(corner).rate(4))),
+            Some(BuildError::SpawnRate {
+                rate: 4,
+                n_cells: 3
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_boundaries() {
+        let err = |b: GridBuilder| b.build().err();
+        let periodic = Boundary::Periodic;
+        let left = Boundaries {
+            left: periodic,
+            ..Boundaries::default()
+        };
+        assert_eq!(
+            err(GridBuilder::new(5, 5).boundaries(left)),
+            Some(BuildError::UnpairedPeriodic)
+        );
+        let bottom = Boundaries {
+            bottom: periodic,
+            ..Boundaries::default()
+        };
+        assert_eq!(
+            err(GridBuilder::new(5, 5).boundaries(bottom)),
+            Some(BuildError::PeriodicBottom)
+        );
+        let sides = Boundaries {
+            left: periodic,
+            right: periodic,
+            bottom: Boundary::Open,
+        };
+        let margolus = GridBuilder::new(5, 5)
+            .boundaries(sides)
+            .scheduler(Scheduler::Margolus);
+        assert_eq!(err(margolus), Some(BuildError::PeriodicOddWidth(5)));
+        assert!(GridBuilder::new(5, 5).boundaries(sides).build().is_ok());
+    }
+
+    #[test]
+    fn builds_valid_configs() {
+        // Heights that aren't a multiple of the thread count are fine
+        let g = GridBuilder::new(3, 1).threads(8).build().unwrap();
+        assert_eq!(g.get_dims(), (3, 1));
+        let g = GridBuilder::new(7, 13)
+            .threads(3)
+            .dispersion(2)
+            .spawn_rate(7)
+            .build()
+            .unwrap();
+        assert_eq!(g.config().n_threads(), 3);
+        assert_eq!(g.config().dispersion(), 2);
+    }
+
+    #[test]
+    fn validates_huge_spawners() {
+        // Only the part of the shape inside the grid is visited
+        let shape = Shape::Rect {
+            x0: 0,
+            y0: 0,
+            x1: usize::MAX,
+            y1: usize::MAX,
+        };
+        let builder = GridBuilder::new(10, 10).spawner(Spawner::new(shape));
+        assert!(builder.clone().build().is_ok());
+        assert_eq!(
+            builder.spawner(Spawner::new(shape).rate(101)).build().err(),
+            Some(BuildError::SpawnRate {
+                rate: 101,
+                n_cells: 100
+            })
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::material::Cell;
+use std::ops::Range;
+
+// Side length of a chunk, in cells
+const CHUNK_SIZE: usize = 32;
+
+// Tracks which rectangular chunks of the grid changed in the previous frame.
+// A chunk can only change if it or one of its neighbours changed in the
+// previous frame, otherwise every cell in it is settled and it can be
+// skipped
+pub struct Chunks {
+    width: usize,
+    height: usize,
+    chunk_width: usize,
+    chunk_height: usize,
+    cols: usize,
+    rows: usize,
+    // Whether the first and last columns of chunks are neighbours, for grids
+    // that wrap around horizontally
+    wrap: bool,
+    changed: Vec<bool>,
+}
+
+impl Chunks {
+    // Chunks are at least as wide as the distance a cell can move sideways
+    // in one frame, so that anything that moves only ever lands in a
+    // neighbouring chunk
+    pub fn new(
+        width: usize,
+        height: usize,
+        max_lateral: usize,
+        wrap: bool,
+    ) -> Chunks {
+        let chunk_width = CHUNK_SIZE.max(max_lateral);
+        let chunk_height = CHUNK_SIZE;
+        let cols = width.div_ceil(chunk_width);
+        let rows = height.div_ceil(chunk_height);
+        Chunks {
+            width,
+            height,
+            chunk_width,
+            chunk_height,
+            cols,
+            rows,
+            wrap,
+            changed: vec![true; cols * rows],
+        }
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    // Computes the index of the chunk containing the cell at (x, y)
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        (y / self.chunk_height) * self.cols + x / self.chunk_width
+    }
+
+    pub fn x_range(&self, col: usize) -> Range<usize> {
+        let start = col * self.chunk_width;
+        start..(start + self.chunk_width).min(self.width)
+    }
+
+    fn y_range(&self, row: usize) -> Range<usize> {
+        let start = row * self.chunk_height;
+        start..(start + self.chunk_height).min(self.height)
+    }
+
+    // Marks the chunk containing the cell at (x, y) as changed, for writes
+    // from outside of the simulation
+    pub fn mark(&mut self, x: usize, y: usize) {
+        let i = self.index(x, y);
+        self.changed[i] = true
+    }
+
+    pub fn mark_all(&mut self) {
+        self.changed.fill(true)
+    }
+
+    // Which chunks changed in the previous frame, to be saved in snapshots
+    pub fn changed(&self) -> &[bool] {
+        &self.changed
+    }
+
+    // Restores flags saved with `changed`. Chunks are laid out differently
+    // when the dispersion changed, in which case every chunk is marked
+    pub fn restore(&mut self, changed: &[bool]) {
+        if changed.len() == self.changed.len() {
+            self.changed.copy_from_slice(changed)
+        } else {
+            self.mark_all()
+        }
+    }
+
+    #[cfg(test)]
+    pub fn n_changed(&self) -> usize {
+        self.changed.iter().filter(|&&c| c).count()
+    }
+
+    // Columns of chunks next to and including `col`
+    fn neighbour_cols(&self, col: usize) -> impl Iterator<Item = usize> {
+        let (cols, wrap) = (self.cols as isize, self.wrap);
+        (col as isize - 1..=col as isize + 1).filter_map(move |c| {
+            if wrap {
+                Some(c.rem_euclid(cols) as usize)
+            } else {
+                (0..cols).contains(&c).then_some(c as usize)
+            }
+        })
+    }
+
+    // Expands the set of flagged chunks to include all of their neighbours
+    fn dilate(&self, flags: &[bool]) -> Vec<bool> {
+        let mut out = vec![false; flags.len()];
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                if !flags[row * self.cols + col] {
+                    continue;
+                }
+                let rows = row.saturating_sub(1)..(row + 2).min(self.rows);
+                for r in rows {
+                    for c in self.neighbour_cols(col) {
+                        out[r * self.cols + c] = true
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    // Chunks that need to be processed this frame
+    pub fn active(&self) -> Vec<bool> {
+        self.dilate(&self.changed)
+    }
+
+    // Computes the flat index range of each row of the chunk
+    fn spans(&self, i: usize) -> impl Iterator<Item = Range<usize>> + '_ {
+        let xs = self.x_range(i % self.cols);
+        self.y_range(i / self.cols)
+            .map(move |y| y * self.width + xs.start..y * self.width + xs.end)
+    }
+
+    // Empties the cells of the active chunks, leaving the rest of the buffer
+    // untouched
+    pub fn clear(&self, active: &[bool], target: &mut [Cell]) {
+        for (i, _) in active.iter().enumerate().filter(|(_, &a)| a) {
+            for span in self.spans(i) {
+                target[span].fill(Cell::EMPTY)
+            }
+        }
+    }
+
+    // Records which chunks changed between the two buffers. Only the active
+    // chunks and their neighbours can have been written to
+    pub fn update(
+        &mut self,
+        active: &[bool],
+        source: &[Cell],
+        target: &[Cell],
+    ) {
+        let touched = self.dilate(active);
+        let mut changed = vec![false; touched.len()];
+        for (i, _) in touched.iter().enumerate().filter(|(_, &t)| t) {
+            changed[i] = self
+                .spans(i)
+                .any(|span| source[span.clone()] != target[span]);
+        }
+        self.changed = changed
+    }
+}
@@ -1,3 +1,7 @@
+use crate::lines::parse_lines;
+use std::sync::Arc;
+
+/// Maps a value in 0..=255 onto the hue wheel, giving a smooth rainbow
 pub fn hsv_to_rgb(h: f64) -> u32 {
     let s = 1.0;
     let v = 1.0;
@@ -22,6 +26,7 @@ pub fn hsv_to_rgb(h: f64) -> u32 {
     (b as u32) | ((g as u32) << 8) | ((r as u32) << 16) | (0xFF << 24)
 }
 
+/// Cycles through pure red, green and blue bands
 pub fn discrete_rgb(h: f64) -> u32 {
     let (h, _) = u8::overflowing_mul(h as u8, 2);
     let (r, g, b) = if h < (u8::MAX / 3) {
@@ -33,3 +38,180 @@ pub fn discrete_rgb(h: f64) -> u32 {
     };
     (b as u32) | ((g as u32) << 8) | ((r as u32) << 16) | (0xFF << 24)
 }
+
+/// Shades of gray, from black at 0 to white at 255
+pub fn grayscale(v: f64) -> u32 {
+    let v = v.clamp(0.0, u8::MAX as f64) as u32;
+    v | (v << 8) | (v << 16) | (0xFF << 24)
+}
+
+/// Splits a `0xAARRGGBB` colour into red, green, blue and alpha bytes,
+/// making it opaque. Empty cells, which render as 0, become black
+pub fn to_rgba(colour: u32) -> [u8; 4] {
+    let [_, r, g, b] = colour.to_be_bytes();
+    [r, g, b, u8::MAX]
+}
+
+/// Maps the colour value of a cell, in 0..=255, to the RGBA colour it is
+/// rendered with, as `0xAARRGGBB`. Implemented for plain functions such as
+/// [`hsv_to_rgb`]
+pub trait ColourMap: Send + Sync {
+    fn convert(&self, v: f64) -> u32;
+}
+
+impl<F: Fn(f64) -> u32 + Send + Sync> ColourMap for F {
+    fn convert(&self, v: f64) -> u32 {
+        self(v)
+    }
+}
+
+impl std::fmt::Debug for dyn ColourMap {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "ColourMap")
+    }
+}
+
+/// Blends linearly between colours spread evenly over 0..=255
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gradient {
+    stops: Vec<u32>,
+}
+
+impl Gradient {
+    /// Takes colours as `0xRRGGBB`. Panics if there are none
+    pub fn new(stops: Vec<u32>) -> Gradient {
+        assert!(!stops.is_empty(), "A gradient needs at least one colour");
+        Gradient { stops }
+    }
+
+    /// Perceptually uniform, from dark purple through teal to yellow
+    pub fn viridis() -> Gradient {
+        Gradient::new(vec![
+            0x440154, 0x482878, 0x3E4989, 0x31688E, 0x26828E, 0x1F9E89,
+            0x35B779, 0x6ECE58, 0xB5DE2B, 0xFDE725,
+        ])
+    }
+
+    /// Perceptually uniform, from black through purple and orange to pale
+    /// yellow
+    pub fn magma() -> Gradient {
+        Gradient::new(vec![
+            0x000004, 0x180F3D, 0x440F76, 0x721F81, 0x9E2F7F, 0xCD4071,
+            0xF1605D, 0xFD9668, 0xFECA8D, 0xFCFDBF,
+        ])
+    }
+
+    /// Browns and beiges, so that piles look like sand
+    pub fn sand() -> Gradient {
+        Gradient::new(vec![0x6B4F2A, 0xA0783C, 0xC2A060, 0xD8BD82, 0xECD9A8])
+    }
+}
+
+impl ColourMap for Gradient {
+    fn convert(&self, v: f64) -> u32 {
+        let last = self.stops.len() - 1;
+        let t = (v / u8::MAX as f64).clamp(0.0, 1.0) * last as f64;
+        let i = (t as usize).min(last);
+        let (from, to) = (self.stops[i], self.stops[(i + 1).min(last)]);
+        let blend = |shift: u32| {
+            let (a, b) = ((from >> shift) & 0xFF, (to >> shift) & 0xFF);
+            let c = a as f64 + (b as f64 - a as f64) * (t - i as f64);
+            (c.round() as u32) << shift
+        };
+        blend(16) | blend(8) | blend(0) | (0xFF << 24)
+    }
+}
+
+// Parses an RGB colour written as six hex digits, such as `c2b280`
+pub(crate) fn parse_hex(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 16)
+        .ok()
+        .filter(|_| s.len() == 6)
+        .ok_or(format!("Invalid hex colour '{}'", s))
+}
+
+// Parses one hex colour per line, such as `c2b280`. Blank lines and lines
+// starting with `#` are ignored
+impl std::str::FromStr for Gradient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let stops = parse_lines(s, parse_hex).collect::<Result<Vec<_>, _>>()?;
+        if stops.is_empty() {
+            return Err(String::from("Expected at least one colour"));
+        }
+        Ok(Gradient::new(stops))
+    }
+}
+
+/// Colour maps that can be picked by name
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum NamedColourMap {
+    /// A smooth rainbow, see [`hsv_to_rgb`]
+    #[default]
+    Continuous,
+    /// Bands of pure red, green and blue, see [`discrete_rgb`]
+    Discrete,
+    Grayscale,
+    Viridis,
+    Magma,
+    /// Browns and beiges
+    Sand,
+}
+
+impl NamedColourMap {
+    pub fn colour_map(self) -> Arc<dyn ColourMap> {
+        match self {
+            NamedColourMap::Continuous => Arc::new(hsv_to_rgb),
+            NamedColourMap::Discrete => Arc::new(discrete_rgb),
+            NamedColourMap::Grayscale => Arc::new(grayscale),
+            NamedColourMap::Viridis => Arc::new(Gradient::viridis()),
+            NamedColourMap::Magma => Arc::new(Gradient::magma()),
+            NamedColourMap::Sand => Arc::new(Gradient::sand()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blends_gradients() {
+        let g = Gradient::new(vec![0x000000, 0xFF8000, 0xFFFFFF]);
+        assert_eq!(g.convert(0.0), 0xFF000000);
+        assert_eq!(g.convert(127.5), 0xFFFF8000);
+        assert_eq!(g.convert(255.0), 0xFFFFFFFF);
+        assert_eq!(g.convert(63.75), 0xFF804000);
+        // Values outside of 0..=255 are clamped
+        assert_eq!(g.convert(300.0), 0xFFFFFFFF);
+        assert_eq!(Gradient::new(vec![0x123456]).convert(99.0), 0xFF123456);
+        assert_eq!(Gradient::viridis().convert(0.0), 0xFF440154);
+    }
+
+    #[test]
+    fn parses_colour_lists() {
+        assert_eq!(
+            "# Fire\nff0000\n\nFFFF00\n".parse(),
+            Ok(Gradient::new(vec![0xFF0000, 0xFFFF00]))
+        );
+        assert_eq!(
+            "ff0000\nyellow".parse::<Gradient>(),
+            Err(String::from("Line 2: Invalid hex colour 'yellow'"))
+        );
+        assert!("# Nothing\n".parse::<Gradient>().is_err());
+    }
+
+    #[test]
+    fn splits_into_rgba() {
+        assert_eq!(to_rgba(0xFF2060D0), [0x20, 0x60, 0xD0, 0xFF]);
+        assert_eq!(to_rgba(0), [0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn grayscale_ramps() {
+        assert_eq!(grayscale(0.0), 0xFF000000);
+        assert_eq!(grayscale(128.0), 0xFF808080);
+        assert_eq!(grayscale(255.0), 0xFFFFFFFF);
+    }
+}
@@ -1,32 +1,165 @@
-use crossbeam::scope;
+use crate::builder::GridBuilder;
+use crate::chunks::Chunks;
+use crate::colour::ColourMap;
+use crate::margolus;
+use crate::material::{Cell, Material};
+use crate::pool::{Task, ThreadPool};
+use crate::render::{self, Motion, RenderMode};
+use crate::shape::Shape;
+use crate::snapshot::{Snapshot, SnapshotError};
+use crate::spawner::Spawner;
 use rand::RngCore;
 use rand_chacha::rand_core::SeedableRng;
 use rand_chacha::{self, ChaCha8Rng};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::vec;
 
+/// Default number of cells a liquid can flow sideways in a single frame
+pub const DEFAULT_DISPERSION: usize = 5;
+
+/// Strategy used to split each frame's work between threads
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Scheduler {
+    /// Horizontal ribbons, processed in two passes of alternating halves
+    #[default]
+    Ribbons,
+    /// 2x2 blocks with alternating offsets, split into vertical strips
+    Margolus,
+}
+
+/// What happens to cells that reach an edge of the grid
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Boundary {
+    /// The edge acts as a wall
+    #[default]
+    Solid,
+    /// Cells fall out of the grid and are removed
+    Open,
+    /// Cells leaving one side come back on the opposite side. Only valid for
+    /// the left and right edges, which must both be periodic
+    Periodic,
+}
+
+/// Boundary modes of the edges of a [`Grid`]. The top edge is always solid,
+/// as nothing moves upwards
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Boundaries {
+    pub left: Boundary,
+    pub right: Boundary,
+    pub bottom: Boundary,
+}
+
+impl Boundaries {
+    pub fn is_periodic(&self) -> bool {
+        self.left == Boundary::Periodic
+    }
+
+    /// Whether every edge is solid, which is the default
+    pub fn is_solid(&self) -> bool {
+        *self == Boundaries::default()
+    }
+}
+
+// Number of rows in a ribbon. Ribbons are laid out independently of the
+// number of threads, so that every thread count processes cells in the same
+// order and produces identical grids
+const RIBBON_HEIGHT: usize = 2;
+
+/// Dimensions and simulation parameters of a [`Grid`]
+#[derive(Clone, Debug)]
 pub struct Config {
     width: usize,
     height: usize,
     size: usize,
     ribbon_len: usize,
+    n_ribbons: usize,
+    n_threads: usize,
+    dispersion: usize,
+    boundaries: Boundaries,
 }
 
 impl Config {
     fn new(width: usize, height: usize, n_threads: usize) -> Config {
-        let rem = height % (n_threads * 2);
-        let mut height = height;
-        if rem != 0 {
-            height -= rem;
-            println!("Warning: grid height must be divisible by N_THREADS * 2. Reducing {} to {}", height + rem, height);
-        }
         let size = width * height;
-        let ribbon_len = size / n_threads;
+        let ribbon_len = width * RIBBON_HEIGHT;
+        // The last ribbon is cut short when the height isn't a multiple of
+        // the ribbon height
+        let n_ribbons = height.div_ceil(RIBBON_HEIGHT);
         Config {
             width,
             height,
             size,
             ribbon_len,
+            n_ribbons,
+            n_threads,
+            dispersion: DEFAULT_DISPERSION,
+            boundaries: Boundaries::default(),
+        }
+    }
+
+    // Splits the ribbons into contiguous groups, one per thread, whose sizes
+    // differ by at most one. Threads without any ribbons are left out
+    fn ribbon_groups(&self) -> Vec<Range<usize>> {
+        let n_groups = self.n_threads.min(self.n_ribbons);
+        (0..n_groups)
+            .map(|i| {
+                let start = i * self.n_ribbons / n_groups;
+                let end = (i + 1) * self.n_ribbons / n_groups;
+                start..end
+            })
+            .collect()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn n_threads(&self) -> usize {
+        self.n_threads
+    }
+
+    pub fn dispersion(&self) -> usize {
+        self.dispersion
+    }
+
+    pub fn boundaries(&self) -> Boundaries {
+        self.boundaries
+    }
+
+    // Resolves a column that may lie past the left or right edge. Columns
+    // outside of the grid that don't wrap around give the boundary mode of
+    // the edge they are past. `SOLID` skips looking up the boundary modes
+    // when every edge is known to be solid
+    #[inline(always)]
+    fn lateral_x<const SOLID: bool>(
+        &self,
+        x: isize,
+    ) -> Result<usize, Boundary> {
+        let w = self.width as isize;
+        if SOLID {
+            return match x >= 0 && x < w {
+                true => Ok(x as usize),
+                false => Err(Boundary::Solid),
+            };
+        }
+        let boundary = if x < 0 {
+            self.boundaries.left
+        } else if x >= w {
+            self.boundaries.right
+        } else {
+            return Ok(x as usize);
+        };
+        match boundary {
+            Boundary::Periodic => Ok(x.rem_euclid(w) as usize),
+            b => Err(b),
         }
     }
 
@@ -45,102 +178,310 @@ impl Config {
     }
 }
 
-fn move_lateral(
+// What happened to a cell when it was processed
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Stayed,
+    Moved,
+    // The cell left the grid
+    Removed,
+}
+
+impl From<bool> for Outcome {
+    fn from(moved: bool) -> Outcome {
+        if moved {
+            Outcome::Moved
+        } else {
+            Outcome::Stayed
+        }
+    }
+}
+
+// A cell can be moved into if it is empty now, and nothing else has
+// already moved into it this frame
+fn is_free(
+    source: &[Cell],
+    target: &[Cell],
+    ribbon_i: usize,
+    real_i: usize,
+) -> bool {
+    source[real_i].is_empty() && target[ribbon_i].is_empty()
+}
+
+// Moves the cell into dest, either because dest is free or by swapping
+// places with a lighter material. When dest has already been processed this
+// frame, the lighter cell must have stayed in place for the swap to be valid.
+// Otherwise, it will notice the swap and skip itself when processed
+#[inline(always)]
+fn try_move(
+    source: &[Cell],
+    target: &mut [Cell],
+    ribbon_i: usize,
+    real_i: usize,
+    dest: usize,
+    dest_real: usize,
+    dest_processed: bool,
+) -> bool {
+    let cell = source[real_i];
+    let other = source[dest_real];
+    if is_free(source, target, dest, dest_real) {
+        target[dest] = cell;
+        return true;
+    }
+    let in_place = if dest_processed {
+        target[dest] == other
+    } else {
+        target[dest].is_empty()
+    };
+    let lighter = other.material.density() < cell.material.density();
+    if in_place && lighter && other.material.is_movable() {
+        target[dest] = cell;
+        target[ribbon_i] = other;
+        return true;
+    }
+    false
+}
+
+#[inline(always)]
+fn move_lateral<const SOLID: bool>(
     cfg: &Config,
-    x: usize,
     offset: isize,
-    source: &[u8],
-    target: &mut [u8],
+    source: &[Cell],
+    target: &mut [Cell],
+    ribbon_i: usize,
+    real_i: usize,
+    below_processed: bool,
+) -> Outcome {
+    let (x, _) = cfg.index_to_coords(ribbon_i);
+    let lateral_x = match cfg.lateral_x::<SOLID>(x as isize + offset) {
+        Ok(lateral_x) => lateral_x,
+        Err(Boundary::Open) => return Outcome::Removed,
+        Err(_) => return Outcome::Stayed,
+    };
+    let below_lateral_real = real_i - x + cfg.width + lateral_x;
+    let below_lateral = ribbon_i - x + cfg.width + lateral_x;
+    try_move(
+        source,
+        target,
+        ribbon_i,
+        real_i,
+        below_lateral,
+        below_lateral_real,
+        below_processed,
+    )
+    .into()
+}
+
+fn move_down(
+    cfg: &Config,
+    source: &[Cell],
+    target: &mut [Cell],
     ribbon_i: usize,
     real_i: usize,
+    below_processed: bool,
 ) -> bool {
-    let mut moved = false;
-    if x as isize + offset >= 0 && x as isize + offset < cfg.width as isize {
-        let below_lateral_real =
-            ((real_i + cfg.width) as isize + offset) as usize;
-        if source[below_lateral_real] == 0 {
-            let below_lateral =
-                ((ribbon_i + cfg.width) as isize + offset) as usize;
-            target[below_lateral] = source[real_i];
-            moved = true;
-        }
-    }
-    moved
+    try_move(
+        source,
+        target,
+        ribbon_i,
+        real_i,
+        ribbon_i + cfg.width,
+        real_i + cfg.width,
+        below_processed,
+    )
+}
+
+// Picks which side to try first when moving laterally, so that piles don't
+// skew towards one side
+fn random_offset(rng: &mut ChaCha8Rng) -> isize {
+    if rng.next_u32() & 1 == 0 {
+        1
+    } else {
+        -1
+    }
 }
 
-fn next_pixel(
+// Granular materials fall straight down, then diagonally
+#[inline(always)]
+fn next_powder<const SOLID: bool>(
     cfg: &Config,
-    source: &[u8],
-    target: &mut [u8],
+    source: &[Cell],
+    target: &mut [Cell],
     ribbon_i: usize,
     real_i: usize,
-) {
-    let mut moved = false;
-    let (x, y) = cfg.index_to_coords(ribbon_i);
-    let real_y = cfg.index_to_y(real_i);
-    let within_full = real_y < cfg.height - 1;
-    let within_half_ribbon = y < cfg.ribbon_len / 2;
-    if within_full && within_half_ribbon && source[real_i] != 0 {
-        if source[real_i + cfg.width] == 0 {
-            target[ribbon_i + cfg.width] = source[real_i];
-            moved = true;
+    below_processed: bool,
+    rng: &mut ChaCha8Rng,
+) -> Outcome {
+    let (i, r, p) = (ribbon_i, real_i, below_processed);
+    if move_down(cfg, source, target, i, r, p) {
+        return Outcome::Moved;
+    }
+    let offset = random_offset(rng);
+    match move_lateral::<SOLID>(cfg, offset, source, target, i, r, p) {
+        Outcome::Stayed => {
+            move_lateral::<SOLID>(cfg, -offset, source, target, i, r, p)
         }
-        if !moved {
-            moved = move_lateral(cfg, x, 1, source, target, ribbon_i, real_i)
+        outcome => outcome,
+    }
+}
+
+// Liquids flow sideways along their row, as far as `cfg.dispersion` cells
+fn flow_lateral<const SOLID: bool>(
+    cfg: &Config,
+    offset: isize,
+    source: &[Cell],
+    target: &mut [Cell],
+    ribbon_i: usize,
+    real_i: usize,
+) -> Outcome {
+    let (x, _) = cfg.index_to_coords(ribbon_i);
+    let mut distance = None;
+    for d in 1..=cfg.dispersion as isize {
+        let lateral_x = match cfg.lateral_x::<SOLID>(x as isize + d * offset) {
+            Ok(lateral_x) => lateral_x,
+            Err(Boundary::Open) => return Outcome::Removed,
+            Err(_) => break,
+        };
+        let lateral = ribbon_i - x + lateral_x;
+        let lateral_real = real_i - x + lateral_x;
+        // Don't take the place of anything that could fall into the cell
+        let above_movable = lateral_real >= cfg.width
+            && source[lateral_real - cfg.width].material.is_movable();
+        if above_movable || !is_free(source, target, lateral, lateral_real) {
+            break;
+        }
+        distance = Some(lateral);
+    }
+    if let Some(lateral) = distance {
+        target[lateral] = source[real_i];
+    }
+    distance.is_some().into()
+}
+
+// Liquids fall like granular materials, then spread out along the ground
+fn next_liquid<const SOLID: bool>(
+    cfg: &Config,
+    source: &[Cell],
+    target: &mut [Cell],
+    ribbon_i: usize,
+    real_i: usize,
+    below: Option<bool>,
+    rng: &mut ChaCha8Rng,
+) -> Outcome {
+    let (i, r) = (ribbon_i, real_i);
+    if let Some(p) = below {
+        match next_powder::<SOLID>(cfg, source, target, i, r, p, rng) {
+            Outcome::Stayed => {}
+            outcome => return outcome,
         }
-        if !moved {
-            moved = move_lateral(cfg, x, -1, source, target, ribbon_i, real_i)
+    }
+    let offset = random_offset(rng);
+    match flow_lateral::<SOLID>(cfg, offset, source, target, i, r) {
+        Outcome::Stayed => {
+            flow_lateral::<SOLID>(cfg, -offset, source, target, i, r)
         }
+        outcome => outcome,
     }
-    if moved {
-        target[ribbon_i] = 0;
-    } else if !moved && source[real_i] != 0 {
+}
+
+// `below_processed` is whether the row below has already been processed this
+// frame, which is not the case on the last row of a ribbon half in the first
+// pass of `Grid::propagate`. `SOLID` is whether every edge is solid, which
+// is decided once per frame so that the default case skips the boundary
+// logic. Returns whether the cell was removed
+fn next_pixel<const SOLID: bool>(
+    cfg: &Config,
+    source: &[Cell],
+    target: &mut [Cell],
+    ribbon_i: usize,
+    real_i: usize,
+    below_processed: bool,
+    rng: &mut ChaCha8Rng,
+) -> bool {
+    // Nothing to do for empty cells, or for a cell that has been displaced
+    // by a heavier one from the row above
+    if source[real_i].is_empty() || !target[ribbon_i].is_empty() {
+        return false;
+    }
+    let real_y = cfg.index_to_y(real_i);
+    let within_full = real_y < cfg.height - 1;
+    // Anything resting on a drain or on an open bottom edge leaves the
+    // grid, leaving its old position empty
+    let falls_out = || {
+        if within_full {
+            source[real_i + cfg.width].material == Material::Drain
+        } else {
+            !SOLID && cfg.boundaries.bottom == Boundary::Open
+        }
+    };
+    if source[real_i].material.is_movable() && falls_out() {
+        return true;
+    }
+    // Only defined when there is a row below to move into
+    let below = within_full.then_some(below_processed);
+    let (i, r) = (ribbon_i, real_i);
+    let outcome = match source[real_i].material {
+        Material::Empty | Material::Wall | Material::Drain => Outcome::Stayed,
+        Material::Sand => below.map_or(Outcome::Stayed, |p| {
+            next_powder::<SOLID>(cfg, source, target, i, r, p, rng)
+        }),
+        Material::Water => {
+            next_liquid::<SOLID>(cfg, source, target, i, r, below, rng)
+        }
+        Material::Stone => below
+            .is_some_and(|p| move_down(cfg, source, target, i, r, p))
+            .into(),
+    };
+    // If the cell moved, then its old position is either still empty or
+    // holds the lighter cell it swapped places with
+    if outcome == Outcome::Stayed {
         target[ribbon_i] = source[real_i];
     }
+    outcome == Outcome::Removed
 }
 
-pub struct DoubleBuffer {
-    buf_a: Vec<u8>,
-    buf_b: Vec<u8>,
+struct DoubleBuffer {
+    buf_a: Vec<Cell>,
+    buf_b: Vec<Cell>,
     count: usize,
 }
 
 impl DoubleBuffer {
-    pub fn new(cfg: &Config) -> DoubleBuffer {
+    fn new(cfg: &Config) -> DoubleBuffer {
         // Add a row of padding at the bottom
         DoubleBuffer {
-            buf_a: vec![0_u8; cfg.size],
-            buf_b: vec![0_u8; cfg.size],
+            buf_a: vec![Cell::EMPTY; cfg.size],
+            buf_b: vec![Cell::EMPTY; cfg.size],
             count: 0,
         }
     }
 
-    pub fn get_front(&self) -> &Vec<u8> {
-        if self.count % 2 == 0 {
+    fn get_front(&self) -> &Vec<Cell> {
+        if self.count.is_multiple_of(2) {
             &self.buf_a
         } else {
             &self.buf_b
         }
     }
 
-    fn get_front_mut(&mut self) -> &mut Vec<u8> {
-        if self.count % 2 == 0 {
+    fn get_front_mut(&mut self) -> &mut Vec<Cell> {
+        if self.count.is_multiple_of(2) {
             &mut self.buf_a
         } else {
             &mut self.buf_b
         }
     }
 
-    fn get_back_mut(&mut self) -> &mut Vec<u8> {
-        if self.count % 2 == 0 {
+    fn get_back_mut(&mut self) -> &mut Vec<Cell> {
+        if self.count.is_multiple_of(2) {
             &mut self.buf_b
         } else {
             &mut self.buf_a
         }
     }
 
-    fn get_pair(&mut self) -> (&Vec<u8>, &mut Vec<u8>) {
-        if self.count % 2 == 0 {
+    fn get_pair(&mut self) -> (&Vec<Cell>, &mut Vec<Cell>) {
+        if self.count.is_multiple_of(2) {
             (&self.buf_a, &mut self.buf_b)
         } else {
             (&self.buf_b, &mut self.buf_a)
@@ -151,16 +492,36 @@ impl DoubleBuffer {
         self.count += 1
     }
 
-    fn empty_back(&mut self) {
-        self.get_back_mut().fill(0)
+    // Both buffers get the same cells, as chunks that don't change are
+    // expected to match between them
+    fn restore(&mut self, cells: &[Cell], count: usize) {
+        self.buf_a.copy_from_slice(cells);
+        self.buf_b.copy_from_slice(cells);
+        self.count = count
     }
 }
 
+pub(crate) const WALL_COLOUR: u32 = 0xFF808080;
+pub(crate) const DRAIN_COLOUR: u32 = 0xFF303030;
+
+/// A double-buffered grid of cells, advanced one frame at a time by a pool of
+/// `n_threads` worker threads
 pub struct Grid {
     cfg: Arc<Config>,
     buf: DoubleBuffer,
+    seed: u64,
     rng: ChaCha8Rng,
-    convert_colour: fn(f64) -> u32,
+    colour_map: Arc<dyn ColourMap>,
+    render_mode: RenderMode,
+    // Only kept up to date when the render mode needs it
+    motion: Motion,
+    scheduler: Scheduler,
+    pool: ThreadPool,
+    chunks: Chunks,
+    // Each spawner along with the points of its shape inside the grid
+    spawners: Vec<(Spawner, Vec<(usize, usize)>)>,
+    // Number of cells removed during the last frame
+    n_removed: usize,
 }
 
 fn count_leading_whitespace(s: &str) -> usize {
@@ -197,104 +558,477 @@ impl std::fmt::Display for Grid {
     }
 }
 
-fn generate_target_ribbons(
-    target: &mut [u8],
+/// Parses text in the format printed by [`Grid`], one row per line with
+/// cells separated by whitespace. Blank lines are ignored, so indented text
+/// blocks can be used as is. Returns the dimensions and the cells, row by
+/// row from the top
+pub fn parse_cells(s: &str) -> Result<((usize, usize), Vec<Cell>), String> {
+    let mut width = None;
+    let mut cells = vec![];
+    let lines = s.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+    for (i, line) in lines {
+        let err = |e| format!("Line {}: {}", i + 1, e);
+        let row = line
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<Cell>, _>>()
+            .map_err(err)?;
+        match width {
+            Some(w) if w != row.len() => {
+                return Err(err(format!(
+                    "Expected {} cells, got {}",
+                    w,
+                    row.len()
+                )))
+            }
+            _ => width = Some(row.len()),
+        }
+        cells.extend(row);
+    }
+    let width = width.ok_or("Expected at least one row")?;
+    Ok(((width, cells.len() / width), cells))
+}
+
+// Creates a grid with the default configuration, see `parse_cells`
+impl FromStr for Grid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ((width, height), cells) = parse_cells(s)?;
+        let mut grid = GridBuilder::new(width, height)
+            .build()
+            .map_err(|e| e.to_string())?;
+        grid.set_cells(&cells);
+        Ok(grid)
+    }
+}
+
+// Splits the target buffer, shifted by `start`, into one slice per group of
+// ribbons. Slices at the end are shorter if the buffer runs out
+fn generate_target_ribbons<'a>(
+    target: &'a mut [Cell],
     start: usize,
     ribbon_len: usize,
-) -> Vec<&mut [u8]> {
-    let (_, target_shifted) = target.split_at_mut(start);
-    target_shifted.chunks_mut(ribbon_len).collect()
+    groups: &[Range<usize>],
+) -> Vec<&'a mut [Cell]> {
+    let (_, mut rest) = target.split_at_mut(start);
+    let mut slices = vec![];
+    for group in groups {
+        let len = (group.len() * ribbon_len).min(rest.len());
+        let (slice, tail) = rest.split_at_mut(len);
+        slices.push(slice);
+        rest = tail;
+    }
+    slices
 }
 
 impl Grid {
-    pub fn new(
+    // Creates an empty grid without checking the arguments, see
+    // `GridBuilder` for the public constructor
+    pub(crate) fn new(
         width: usize,
         height: usize,
         n_threads: usize,
         seed: u64,
-        convert_colour: fn(f64) -> u32,
+        colour_map: Arc<dyn ColourMap>,
     ) -> Grid {
         let cfg = Arc::new(Config::new(width, height, n_threads));
         let buf = DoubleBuffer::new(&cfg);
         let rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
-        Grid {
+        let mut grid = Grid {
             cfg,
             buf,
+            seed,
             rng,
-            convert_colour,
-        }
+            colour_map,
+            render_mode: RenderMode::default(),
+            motion: Motion::default(),
+            scheduler: Scheduler::default(),
+            pool: ThreadPool::new(n_threads),
+            chunks: Chunks::new(width, height, DEFAULT_DISPERSION, false),
+            spawners: vec![],
+            n_removed: 0,
+        };
+        grid.set_spawners(vec![Spawner::top_row(width)]);
+        grid
     }
 
+    /// Maps a colour value to RGBA with the grid's colour map
     pub fn convert_colour(&self, v: f64) -> u32 {
-        (self.convert_colour)(v)
+        self.colour_map.convert(v)
     }
 
-    pub fn get_front(&self) -> &Vec<u8> {
+    /// Replaces the colour map used for rendering
+    pub fn set_colour_map(&mut self, colour_map: Arc<dyn ColourMap>) {
+        self.colour_map = colour_map
+    }
+
+    /// Changes what the colours of rendered cells are derived from. Motion
+    /// is tracked from the next frame on
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.motion = match mode.tracks_motion() {
+            true => Motion::new(self.cfg.size),
+            false => Motion::default(),
+        };
+        self.render_mode = mode
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+
+    pub(crate) fn motion(&self) -> &Motion {
+        &self.motion
+    }
+
+    /// Computes the RGBA colour of every cell of the current frame in the
+    /// grid's render mode, row by row from the top
+    pub fn render(&self) -> Vec<u32> {
+        render::render(self)
+    }
+
+    /// Computes the RGBA colour a cell is rendered with from its colour
+    /// value, regardless of the render mode
+    pub fn cell_colour(&self, cell: Cell) -> u32 {
+        match cell.material {
+            Material::Empty => 0,
+            Material::Wall => WALL_COLOUR,
+            Material::Drain => DRAIN_COLOUR,
+            _ => self.convert_colour(cell.colour as f64),
+        }
+    }
+
+    /// Cells of the current frame, row by row from the top
+    pub fn get_front(&self) -> &Vec<Cell> {
         self.buf.get_front()
     }
 
+    /// Width and height of the grid, in cells
     pub fn get_dims(&self) -> (usize, usize) {
         self.cfg.get_dims()
     }
 
+    pub fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    /// Seed the grid was created with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Number of frames the grid has been advanced by
+    pub fn frame(&self) -> usize {
+        self.buf.count
+    }
+
+    /// Panics if (x, y) is outside of the grid
+    pub fn get_cell(&self, x: usize, y: usize) -> Cell {
+        let (w, h) = self.get_dims();
+        assert!(x < w && y < h, "({}, {}) is outside of the grid", x, y);
+        self.get_front()[y * w + x]
+    }
+
+    /// Liquids flow at most one cell per frame with the Margolus scheduler,
+    /// regardless of the dispersion. Panics when switching to the Margolus
+    /// scheduler on a grid that wraps around and has an odd width
+    pub fn set_scheduler(&mut self, scheduler: Scheduler) {
+        assert!(
+            scheduler != Scheduler::Margolus
+                || !self.cfg.boundaries.is_periodic()
+                || self.cfg.width.is_multiple_of(2),
+            "Periodic edges need an even width with the Margolus scheduler"
+        );
+        self.scheduler = scheduler
+    }
+
+    /// Sets how many cells a liquid can flow sideways in a single frame
+    pub fn set_dispersion(&mut self, dispersion: usize) {
+        Arc::make_mut(&mut self.cfg).dispersion = dispersion;
+        self.reset_chunks()
+    }
+
+    // Periodic boundaries require an even width with the Margolus
+    // scheduler, which `GridBuilder` checks
+    pub(crate) fn set_boundaries(&mut self, boundaries: Boundaries) {
+        Arc::make_mut(&mut self.cfg).boundaries = boundaries;
+        self.reset_chunks()
+    }
+
+    fn reset_chunks(&mut self) {
+        let cfg = &self.cfg;
+        let wrap = cfg.boundaries.is_periodic();
+        self.chunks = Chunks::new(cfg.width, cfg.height, cfg.dispersion, wrap)
+    }
+
+    /// Replaces the spawners used by [`Grid::spawn`]. By default sand is
+    /// dropped along the top row
+    pub fn set_spawners(&mut self, spawners: Vec<Spawner>) {
+        let dims = self.get_dims();
+        self.spawners = spawners
+            .into_iter()
+            .map(|s| (s, s.shape.points_within(dims)))
+            .collect()
+    }
+
+    pub fn spawners(&self) -> impl Iterator<Item = &Spawner> {
+        self.spawners.iter().map(|(s, _)| s)
+    }
+
+    /// Lets every spawner drop its cells for the given frame number
     pub fn spawn(&mut self, frame: u32) {
+        let w = self.cfg.width;
         let source = self.buf.get_front_mut();
-        for _ in 0..(self.cfg.width / 20 + 1) {
-            let i = self.rng.next_u32() as usize % self.cfg.width;
-            if source[i] == 0 {
-                source[i] = ((frame / 5) % 254 + 1) as u8;
+        for (spawner, points) in &self.spawners {
+            if points.is_empty() {
+                continue;
+            }
+            for _ in 0..spawner.rate {
+                let n = self.rng.next_u32() as usize % points.len();
+                let (x, y) = points[n];
+                if source[y * w + x].is_empty() {
+                    source[y * w + x] = spawner.cell(frame, &mut self.rng);
+                    self.chunks.mark(x, y);
+                }
             }
         }
     }
 
-    fn propagate_half(&mut self, offset: usize) {
+    // Each thread is given a contiguous group of ribbons, and processes one
+    // half of each of them. Cells in chunks that aren't active are skipped.
+    // Returns the number of cells removed
+    fn propagate_half<const SOLID: bool>(
+        &mut self,
+        offset: usize,
+        frame_seed: u64,
+        active: &[bool],
+    ) -> usize {
         let (source, target) = self.buf.get_pair();
-        let mut target_ribbons =
-            generate_target_ribbons(target, offset, self.cfg.ribbon_len);
         let ribbon_len = self.cfg.ribbon_len;
-        scope(|s| {
-            for (i, target) in target_ribbons.iter_mut().enumerate().rev() {
-                let cfg = Arc::clone(&self.cfg);
-                s.spawn(move |_| {
-                    for j in (0..(ribbon_len / 2)).rev() {
-                        let real_index = i * ribbon_len + j + offset;
-                        next_pixel(&cfg, source, target, j, real_index)
+        let half_rows = RIBBON_HEIGHT / 2;
+        let groups = self.cfg.ribbon_groups();
+        let target_groups =
+            generate_target_ribbons(target, offset, ribbon_len, &groups);
+        let chunks = &self.chunks;
+        let n_removed = &AtomicUsize::new(0);
+        let mut tasks: Vec<Task> = vec![];
+        for (group, target) in groups.iter().zip(target_groups) {
+            let cfg = Arc::clone(&self.cfg);
+            let group_start = group.start * ribbon_len;
+            let n_ribbons = group.len();
+            tasks.push(Box::new(move || {
+                let mut rng = ChaCha8Rng::seed_from_u64(frame_seed);
+                let mut removed = 0;
+                for k in (0..n_ribbons).rev() {
+                    for row in (0..half_rows).rev() {
+                        let row_start = k * ribbon_len + row * cfg.width;
+                        // The last ribbon can be cut short
+                        if row_start >= target.len() {
+                            continue;
+                        }
+                        let real_row_start = group_start + row_start + offset;
+                        let y = cfg.index_to_y(real_row_start);
+                        // Each row draws from its own stream, so the random
+                        // choices don't depend on how rows are split between
+                        // threads
+                        rng.set_stream(y as u64);
+                        rng.set_word_pos(0);
+                        // The row below the last row of a ribbon half is
+                        // only processed beforehand in the second pass
+                        let below_processed =
+                            offset == 0 || row + 1 < half_rows;
+                        for col in (0..chunks.cols()).rev() {
+                            let x_range = chunks.x_range(col);
+                            if !active[chunks.index(x_range.start, y)] {
+                                continue;
+                            }
+                            for x in x_range.rev() {
+                                removed += next_pixel::<SOLID>(
+                                    &cfg,
+                                    source,
+                                    target,
+                                    row_start + x,
+                                    real_row_start + x,
+                                    below_processed,
+                                    &mut rng,
+                                )
+                                    as usize
+                            }
+                        }
                     }
-                });
-            }
-        })
-        .unwrap();
+                }
+                n_removed.fetch_add(removed, Ordering::Relaxed);
+            }));
+        }
+        self.pool.run(tasks);
+        n_removed.load(Ordering::Relaxed)
     }
 
+    // Only the active chunks of the back buffer are emptied and processed.
+    // Every other chunk was settled in the previous frame, so the back buffer
+    // already holds the same cells as the front buffer there
     fn propagate(&mut self) {
-        self.buf.empty_back();
-        self.propagate_half(self.cfg.ribbon_len / 2);
-        self.propagate_half(0);
+        let active = self.chunks.active();
+        self.chunks.clear(&active, self.buf.get_back_mut());
+        let frame_seed = self.rng.next_u64();
+        let half = self.cfg.ribbon_len / 2;
+        self.n_removed = if self.cfg.boundaries.is_solid() {
+            self.propagate_half::<true>(half, frame_seed, &active)
+                + self.propagate_half::<true>(0, frame_seed, &active)
+        } else {
+            self.propagate_half::<false>(half, frame_seed, &active)
+                + self.propagate_half::<false>(0, frame_seed, &active)
+        };
+        let (source, target) = self.buf.get_pair();
+        self.chunks.update(&active, source, target);
     }
 
+    fn propagate_margolus(&mut self) {
+        let frame_seed = self.rng.next_u64();
+        let frame = self.buf.count;
+        let (source, target) = self.buf.get_pair();
+        self.n_removed = margolus::propagate(
+            source,
+            target,
+            self.cfg.get_dims(),
+            self.cfg.boundaries,
+            &self.pool,
+            frame,
+            frame_seed,
+        );
+        // Every cell is processed, so there is nothing to track
+        self.chunks.mark_all();
+    }
+
+    /// Advances the simulation by one frame
     pub fn next(&mut self) {
-        self.propagate();
+        match self.scheduler {
+            Scheduler::Ribbons => self.propagate(),
+            Scheduler::Margolus => self.propagate_margolus(),
+        }
+        if self.render_mode.tracks_motion() {
+            let (source, target) = self.buf.get_pair();
+            self.motion.update(source, target);
+        }
         self.buf.switch_buffers();
     }
 
+    /// Panics if (x, y) is outside of the grid
+    pub fn set_cell(&mut self, x: usize, y: usize, cell: Cell) {
+        let (w, h) = self.get_dims();
+        assert!(x < w && y < h, "({}, {}) is outside of the grid", x, y);
+        let buf = self.buf.get_front_mut();
+        buf[y * w + x] = cell;
+        self.chunks.mark(x, y)
+    }
+
+    /// Replaces every cell of the current frame, row by row from the top.
+    /// Panics if the number of cells doesn't match the grid
+    pub fn set_cells(&mut self, cells: &[Cell]) {
+        assert_eq!(cells.len(), self.cfg.size, "Wrong number of cells");
+        let count = self.buf.count;
+        self.buf.restore(cells, count);
+        self.chunks.mark_all()
+    }
+
+    /// Sets every cell covered by the shape, ignoring any points outside of
+    /// the grid
+    pub fn draw(&mut self, shape: &Shape, cell: Cell) {
+        for (x, y) in shape.points_within(self.get_dims()) {
+            self.set_cell(x, y, cell)
+        }
+    }
+
+    pub fn draw_wall(&mut self, shape: &Shape) {
+        self.draw(shape, Cell::new(Material::Wall, 0))
+    }
+
+    pub fn draw_drain(&mut self, shape: &Shape) {
+        self.draw(shape, Cell::new(Material::Drain, 0))
+    }
+
+    /// Number of cells removed by drains or open edges during the last frame
+    pub fn n_removed(&self) -> usize {
+        self.n_removed
+    }
+
+    /// Captures everything needed to continue the simulation exactly,
+    /// including the position of the random number generator. The
+    /// configuration and spawners aren't included
+    pub fn snapshot(&self) -> Snapshot {
+        let (width, height) = self.get_dims();
+        Snapshot {
+            width,
+            height,
+            seed: self.seed,
+            rng_key: self.rng.get_seed(),
+            rng_stream: self.rng.get_stream(),
+            rng_word_pos: self.rng.get_word_pos(),
+            frame: self.buf.count,
+            cells: self.get_front().clone(),
+            changed_chunks: self.chunks.changed().to_vec(),
+        }
+    }
+
+    /// Replaces the cells, random number generator and frame counter with
+    /// the ones from a snapshot of a grid of the same dimensions
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), SnapshotError> {
+        let (expected, found) = (self.get_dims(), snapshot.get_dims());
+        if expected != found {
+            return Err(SnapshotError::DimensionMismatch { expected, found });
+        }
+        self.buf.restore(&snapshot.cells, snapshot.frame);
+        self.seed = snapshot.seed;
+        self.rng = ChaCha8Rng::from_seed(snapshot.rng_key);
+        self.rng.set_stream(snapshot.rng_stream);
+        self.rng.set_word_pos(snapshot.rng_word_pos);
+        self.chunks.restore(&snapshot.changed_chunks);
+        Ok(())
+    }
+
+    /// Writes a binary snapshot of the grid, see [`Snapshot`]
+    pub fn save(&self, w: impl Write) -> io::Result<()> {
+        self.snapshot().write(w)
+    }
+
+    /// Reads a binary snapshot written by [`Grid::save`] and restores it
+    pub fn load(&mut self, r: impl Read) -> Result<(), SnapshotError> {
+        self.restore(Snapshot::read(r)?)
+    }
+
     #[cfg(test)]
     fn set_px(&mut self, x: usize, y: usize, v: u8) {
-        let (w, _) = self.get_dims();
-        let buf = self.buf.get_front_mut();
-        buf[y * w + x] = v
+        self.set_cell(x, y, Cell::new(Material::Sand, v))
+    }
+
+    #[cfg(test)]
+    fn n_changed_chunks(&self) -> usize {
+        self.chunks.n_changed()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::spawner::ColourSchedule;
     use insta::assert_snapshot;
 
-    const DUMMY_CONVERT_COLOUR: fn(f64) -> u32 = |v: f64| v as u32;
+    fn dummy_colour_map() -> Arc<dyn ColourMap> {
+        Arc::new(|v: f64| v as u32)
+    }
+
+    impl Grid {
+        fn from_text(text: &str, n_threads: usize) -> Grid {
+            let ((w, h), cells) = parse_cells(text).unwrap();
+            let mut g = Grid::new(w, h, n_threads, 0, dummy_colour_map());
+            g.set_cells(&cells);
+            g
+        }
+    }
 
     #[test]
     fn stays_on_ground() {
-        let mut g = Grid::new(3, 2, 1, 0, DUMMY_CONVERT_COLOUR);
+        let mut g = Grid::new(3, 2, 1, 0, dummy_colour_map());
         g.set_px(1, 1, 2);
         assert_snapshot!(g.to_string(), @r#"
             0    0    0
@@ -309,7 +1043,7 @@ mod tests {
 
     #[test]
     fn falls_straight() {
-        let mut g = Grid::new(3, 2, 1, 0, DUMMY_CONVERT_COLOUR);
+        let mut g = Grid::new(3, 2, 1, 0, dummy_colour_map());
         g.set_px(1, 0, 2);
         assert_snapshot!(g.to_string(), @r#"
             0    2    0
@@ -324,7 +1058,7 @@ mod tests {
 
     #[test]
     fn falls_laterally() {
-        let mut g = Grid::new(3, 2, 1, 0, DUMMY_CONVERT_COLOUR);
+        let mut g = Grid::new(3, 2, 1, 0, dummy_colour_map());
         g.set_px(0, 1, 255);
         g.set_px(0, 0, 255);
         assert_snapshot!(g.to_string(), @r#"
@@ -340,7 +1074,7 @@ mod tests {
 
     #[test]
     fn falls_laterally_both() {
-        let mut g = Grid::new(3, 6, 1, 0, DUMMY_CONVERT_COLOUR);
+        let mut g = Grid::new(3, 6, 1, 0, dummy_colour_map());
         g.set_px(1, 4, 3);
         g.set_px(1, 2, 3);
         g.set_px(1, 0, 3);
@@ -365,7 +1099,7 @@ mod tests {
 
     #[test]
     fn falls_laterally_both_multithreaded() {
-        let mut g = Grid::new(3, 6, 3, 0, DUMMY_CONVERT_COLOUR);
+        let mut g = Grid::new(3, 6, 3, 0, dummy_colour_map());
         g.set_px(1, 4, 3);
         g.set_px(1, 2, 3);
         g.set_px(1, 0, 3);
@@ -399,4 +1133,921 @@ mod tests {
             3    3    3
         "#);
     }
+
+    #[test]
+    fn stone_does_not_fall_laterally() {
+        let mut g = Grid::new(3, 2, 1, 0, dummy_colour_map());
+        g.set_cell(0, 1, Cell::new(Material::Stone, 4));
+        g.set_cell(0, 0, Cell::new(Material::Stone, 4));
+        assert_snapshot!(g.to_string(), @r#"
+            4s    0    0
+            4s    0    0
+        "#);
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+            4s    0    0
+            4s    0    0
+        "#);
+    }
+
+    #[test]
+    fn wall_blocks_falling_grains() {
+        let mut g = Grid::from_text(
+            "
+            0    2    0
+            0   0#    0
+            0    0    0
+            0    0    0
+            ",
+            1,
+        );
+        assert_snapshot!(g.to_string(), @r#"
+             0    2    0
+             0   0#    0
+             0    0    0
+             0    0    0
+        "#);
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0
+             0   0#    2
+             0    0    0
+             0    0    0
+        "#);
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0
+             0   0#    0
+             0    0    2
+             0    0    0
+        "#);
+    }
+
+    #[test]
+    fn does_not_lose_grains() {
+        let mut g = Grid::from_text(
+            "
+            2    0    3
+            1    0    1
+            ",
+            1,
+        );
+        assert_snapshot!(g.to_string(), @r#"
+            2    0    3
+            1    0    1
+        "#);
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+            2    0    0
+            1    3    1
+        "#);
+    }
+
+    fn water(colour: u8) -> Cell {
+        Cell::new(Material::Water, colour)
+    }
+
+    #[test]
+    fn parses_text() {
+        let text = "
+              7    0   3w
+             0#   4s   0d
+        ";
+        let g: Grid = text.parse().unwrap();
+        assert_eq!(g.get_dims(), (3, 2));
+        assert_eq!(g.get_cell(0, 0), Cell::new(Material::Sand, 7));
+        assert_eq!(g.get_cell(2, 0), water(3));
+        assert_eq!(g.get_cell(0, 1), Cell::new(Material::Wall, 0));
+        assert_eq!(g.get_cell(1, 1), Cell::new(Material::Stone, 4));
+        assert_eq!(g.get_cell(2, 1), Cell::new(Material::Drain, 0));
+        assert_eq!(
+            g.to_string().parse::<Grid>().unwrap().to_string(),
+            g.to_string()
+        );
+        let err = |s: &str| s.parse::<Grid>().err().unwrap();
+        assert_eq!(err("1 2\n3"), "Line 2: Expected 2 cells, got 1");
+        assert_eq!(err("1 2x"), "Line 1: Unknown material symbol 'x'");
+        assert_eq!(err("\n256"), "Line 2: Invalid cell '256'");
+        assert_eq!(err("  \n"), "Expected at least one row");
+    }
+
+    #[test]
+    fn text_scenes_run_on_multiple_threads() {
+        let text = "
+            0   2s    0    0    0    0
+            0   1w    0    0    0    3
+            0   0#   0#    0   0#    0
+            0    0    0    0    0    0
+        ";
+        let mut g = Grid::from_text(text, 2);
+        g.next();
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0    0    0
+             0   2s    0    0    0    0
+             0   0#   0#    0   0#    0
+            1w    0    0    0    0    3
+        "#);
+    }
+
+    #[test]
+    fn falls_laterally_liquid() {
+        let mut g = Grid::new(5, 2, 1, 0, dummy_colour_map());
+        g.set_cell(2, 1, water(1));
+        g.set_cell(2, 0, water(2));
+        assert_snapshot!(g.to_string(), @r#"
+            0    0   2w    0    0
+            0    0   1w    0    0
+        "#);
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0    0
+             0    0    0   2w   1w
+        "#);
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0    0
+            2w    0    0    0   1w
+        "#);
+    }
+
+    #[test]
+    fn falls_laterally_liquid_dispersion() {
+        let mut g = Grid::new(8, 2, 1, 0, dummy_colour_map());
+        g.set_dispersion(3);
+        g.set_cell(0, 1, water(1));
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0    0    0    0    0
+             0    0    0   1w    0    0    0    0
+        "#);
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0    0    0    0    0
+             0    0    0    0    0    0   1w    0
+        "#);
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0    0    0    0    0
+             0    0    0   1w    0    0    0    0
+        "#);
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0    0    0    0    0
+            1w    0    0    0    0    0    0    0
+        "#);
+    }
+
+    #[test]
+    fn falls_laterally_liquid_blocked() {
+        let mut g = Grid::new(5, 2, 1, 0, dummy_colour_map());
+        g.set_cell(1, 1, Cell::new(Material::Wall, 0));
+        g.set_cell(2, 1, water(1));
+        g.set_cell(3, 1, Cell::new(Material::Wall, 0));
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0    0
+             0   0#   1w   0#    0
+        "#);
+    }
+
+    #[test]
+    fn falls_laterally_liquid_multithreaded() {
+        let mut g = Grid::new(4, 6, 3, 0, dummy_colour_map());
+        for y in 0..6 {
+            g.set_cell(0, y, water(y as u8 + 1));
+        }
+        assert_snapshot!(g.to_string(), @r#"
+            1w    0    0    0
+            2w    0    0    0
+            3w    0    0    0
+            4w    0    0    0
+            5w    0    0    0
+            6w    0    0    0
+        "#);
+        for _ in 0..10 {
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0
+             0    0    0    0
+             0    0    0    0
+             0    0    0    0
+            4w    0    0   1w
+            2w   5w   6w   3w
+        "#);
+    }
+
+    #[test]
+    fn sinks_through_liquid() {
+        let mut g = Grid::new(3, 6, 1, 0, dummy_colour_map());
+        for y in 3..6 {
+            for x in 0..3 {
+                g.set_cell(x, y, water(1));
+            }
+        }
+        g.set_px(1, 0, 2);
+        g.set_px(1, 1, 3);
+        assert_snapshot!(g.to_string(), @r#"
+             0    2    0
+             0    3    0
+             0    0    0
+            1w   1w   1w
+            1w   1w   1w
+            1w   1w   1w
+        "#);
+        for _ in 0..6 {
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0
+             0    0    0
+            1w   1w    0
+            1w   1w   1w
+            1w   1w   1w
+            1w    3    2
+        "#);
+    }
+
+    #[test]
+    fn sinks_through_liquid_multithreaded() {
+        let mut g = Grid::new(3, 8, 4, 0, dummy_colour_map());
+        for y in 2..8 {
+            for x in 0..3 {
+                g.set_cell(x, y, water(1));
+            }
+        }
+        g.set_px(1, 0, 2);
+        g.set_px(1, 1, 3);
+        for _ in 0..10 {
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0
+            1w    0   1w
+            1w   1w   1w
+            1w   1w   1w
+            1w   1w   1w
+            1w   1w   1w
+            1w   1w   1w
+            1w    3    2
+        "#);
+    }
+
+    #[test]
+    fn stone_sinks_through_sand() {
+        let mut g = Grid::new(1, 4, 2, 0, dummy_colour_map());
+        g.set_cell(0, 0, Cell::new(Material::Stone, 9));
+        g.set_px(0, 1, 1);
+        g.set_px(0, 2, 2);
+        g.set_px(0, 3, 3);
+        for _ in 0..3 {
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+             1
+             2
+             3
+            9s
+        "#);
+    }
+
+    #[test]
+    fn draws_walls() {
+        let mut g = Grid::new(7, 6, 1, 0, dummy_colour_map());
+        g.draw_wall(&Shape::Rect {
+            x0: 0,
+            y0: 5,
+            x1: 6,
+            y1: 5,
+        });
+        g.draw_wall(&Shape::Line {
+            x0: 0,
+            y0: 0,
+            x1: 2,
+            y1: 2,
+        });
+        g.draw_wall(&Shape::Line {
+            x0: 6,
+            y0: 0,
+            x1: 4,
+            y1: 2,
+        });
+        g.draw_wall(&Shape::Circle { x: 5, y: 4, r: 1 });
+        assert_snapshot!(g.to_string(), @r#"
+            0#    0    0    0    0    0   0#
+             0   0#    0    0    0   0#    0
+             0    0   0#    0   0#    0    0
+             0    0    0    0    0   0#    0
+             0    0    0    0   0#   0#   0#
+            0#   0#   0#   0#   0#   0#   0#
+        "#);
+    }
+
+    #[test]
+    fn funnel_channels_grains() {
+        let mut g = Grid::new(7, 8, 2, 0, dummy_colour_map());
+        g.draw_wall(&Shape::Line {
+            x0: 0,
+            y0: 1,
+            x1: 2,
+            y1: 3,
+        });
+        g.draw_wall(&Shape::Line {
+            x0: 6,
+            y0: 1,
+            x1: 4,
+            y1: 3,
+        });
+        for x in 1..6 {
+            g.set_px(x, 0, x as u8);
+        }
+        for _ in 0..12 {
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0    0    0    0
+            0#    0    0    0    0    0   0#
+             0   0#    0    0    0   0#    0
+             0    0   0#    0   0#    0    0
+             0    0    0    0    0    0    0
+             0    0    0    0    0    0    0
+             0    0    0    0    0    0    0
+             1    2    0    4    3    0    5
+        "#);
+    }
+
+    #[test]
+    fn falls_laterally_random() {
+        let mut g = Grid::new(5, 4, 2, 0, dummy_colour_map());
+        for _ in 0..6 {
+            g.set_px(2, 0, 1);
+            g.next();
+        }
+        for _ in 0..4 {
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+            0    0    0    0    0
+            0    0    0    0    0
+            0    0    1    1    0
+            0    1    1    1    1
+        "#);
+    }
+
+    #[test]
+    fn piles_are_symmetric() {
+        let (w, h) = (61, 32);
+        let mut g = Grid::new(w, h, 2, 0, dummy_colour_map());
+        for _ in 0..400 {
+            g.set_px(w / 2, 0, 1);
+            g.next();
+        }
+        let count = |xs: std::ops::Range<usize>| {
+            let front = g.get_front();
+            xs.flat_map(|x| (0..h).map(move |y| y * w + x))
+                .filter(|&i| !front[i].is_empty())
+                .count()
+        };
+        let (left, right) = (count(0..w / 2), count(w / 2 + 1..w));
+        assert!(left > 0 && right > 0);
+        assert!(left.abs_diff(right) <= (left + right) / 20);
+    }
+
+    fn run_scene(n_threads: usize, seed: u64) -> Vec<Cell> {
+        let mut g = Grid::new(40, 30, n_threads, seed, dummy_colour_map());
+        g.draw_wall(&Shape::Line {
+            x0: 5,
+            y0: 20,
+            x1: 20,
+            y1: 25,
+        });
+        let pool = Shape::Rect {
+            x0: 22,
+            y0: 8,
+            x1: 36,
+            y1: 13,
+        };
+        g.draw(&pool, water(7));
+        g.draw(&Shape::Circle { x: 30, y: 3, r: 2 }, stone(3));
+        for i in 0..120 {
+            g.spawn(i);
+            g.next();
+        }
+        g.get_front().clone()
+    }
+
+    fn stone(colour: u8) -> Cell {
+        Cell::new(Material::Stone, colour)
+    }
+
+    #[test]
+    fn reproducible_across_thread_counts() {
+        let expected = run_scene(1, 42);
+        for n_threads in [2, 4, 8] {
+            assert!(
+                run_scene(n_threads, 42) == expected,
+                "{} threads diverged from 1 thread",
+                n_threads
+            );
+        }
+    }
+
+    #[test]
+    fn seed_changes_result() {
+        assert!(run_scene(4, 1) != run_scene(4, 2));
+    }
+
+    #[test]
+    fn keeps_prime_height() {
+        let mut g = Grid::new(3, 7, 2, 0, dummy_colour_map());
+        assert_eq!(g.get_dims(), (3, 7));
+        g.set_px(1, 0, 1);
+        g.set_px(1, 3, 2);
+        for _ in 0..6 {
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+            0    0    0
+            0    0    0
+            0    0    0
+            0    0    0
+            0    0    0
+            0    0    0
+            1    2    0
+        "#);
+    }
+
+    #[test]
+    fn more_threads_than_rows() {
+        let mut g = Grid::new(3, 3, 8, 0, dummy_colour_map());
+        g.set_px(0, 0, 1);
+        g.set_px(1, 0, 2);
+        g.set_px(2, 0, 3);
+        g.set_px(1, 1, 4);
+        for _ in 0..3 {
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+            0    0    0
+            0    1    0
+            2    4    3
+        "#);
+    }
+
+    #[test]
+    fn single_row() {
+        let mut g = Grid::new(4, 1, 4, 0, dummy_colour_map());
+        g.set_px(1, 0, 1);
+        g.set_cell(2, 0, water(2));
+        g.next();
+        assert_snapshot!(g.to_string(), @"0    1    0   2w");
+    }
+
+    #[test]
+    fn reproducible_with_uneven_ribbons() {
+        for (w, h) in [(13, 17), (5, 31), (29, 3)] {
+            let run = |n_threads| {
+                let mut g = Grid::new(w, h, n_threads, 3, dummy_colour_map());
+                g.draw(
+                    &Shape::Rect {
+                        x0: 0,
+                        y0: h - 1,
+                        x1: w / 2,
+                        y1: h - 1,
+                    },
+                    water(1),
+                );
+                for i in 0..(2 * h as u32) {
+                    g.spawn(i);
+                    g.next();
+                }
+                g.get_front().clone()
+            };
+            let expected = run(1);
+            for n_threads in [2, 3, 4, 8, 64] {
+                assert!(
+                    run(n_threads) == expected,
+                    "{}x{} with {} threads diverged from 1 thread",
+                    w,
+                    h,
+                    n_threads
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn settled_chunks_sleep() {
+        let mut g = Grid::new(100, 70, 2, 0, dummy_colour_map());
+        g.draw(
+            &Shape::Circle {
+                x: 50,
+                y: 20,
+                r: 10,
+            },
+            Cell::new(Material::Sand, 1),
+        );
+        g.draw(
+            &Shape::Rect {
+                x0: 10,
+                y0: 0,
+                x1: 20,
+                y1: 10,
+            },
+            stone(2),
+        );
+        for _ in 0..300 {
+            g.next();
+        }
+        assert_eq!(g.n_changed_chunks(), 0);
+        let settled = g.get_front().clone();
+        g.next();
+        assert!(g.get_front() == &settled);
+        // Writing a cell wakes up its chunk
+        g.set_px(90, 0, 1);
+        assert_eq!(g.n_changed_chunks(), 1);
+        for _ in 0..100 {
+            g.next();
+        }
+        assert_eq!(g.n_changed_chunks(), 0);
+        assert_eq!(g.get_front()[69 * 100 + 90], Cell::new(Material::Sand, 1));
+    }
+
+    #[test]
+    fn sleeping_reproducible_across_thread_counts() {
+        // Spawning keeps the top row awake while the rest of the grid
+        // settles, and the result must not depend on the thread count
+        let run = |n_threads| {
+            let mut g = Grid::new(150, 90, n_threads, 5, dummy_colour_map());
+            for i in 0..400 {
+                if i < 200 {
+                    g.spawn(i);
+                }
+                g.next();
+            }
+            assert_eq!(g.n_changed_chunks(), 0);
+            g.get_front().clone()
+        };
+        let expected = run(1);
+        for n_threads in [3, 8] {
+            assert!(run(n_threads) == expected);
+        }
+    }
+
+    #[test]
+    fn spawners_drop_cells() {
+        let mut g = Grid::new(9, 4, 2, 0, dummy_colour_map());
+        g.set_spawners(vec![
+            Spawner::new(Shape::Point { x: 1, y: 0 })
+                .colour(ColourSchedule::Constant(4)),
+            Spawner::new(Shape::Point { x: 6, y: 0 })
+                .material(Material::Stone)
+                .colour(ColourSchedule::Cycle { period: 2 }),
+        ]);
+        for i in 0..4 {
+            g.spawn(i);
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+            0    0    0    0    0    0   2s    0    0
+            4    0    0    0    0    0    0    0    0
+            0    4    0    0    0    0   1s    0    0
+            0    4    4    0    0    0   1s    0    0
+        "#);
+    }
+
+    #[test]
+    fn drains_remove_grains() {
+        let mut g = Grid::new(3, 4, 1, 0, dummy_colour_map());
+        g.draw_drain(&Shape::Rect {
+            x0: 0,
+            y0: 3,
+            x1: 2,
+            y1: 3,
+        });
+        g.set_px(1, 0, 1);
+        g.set_cell(0, 1, water(2));
+        g.set_cell(2, 2, stone(3));
+        g.next();
+        assert_eq!(g.n_removed(), 1);
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0
+             0    1    0
+            2w    0    0
+            0d   0d   0d
+        "#);
+        g.next();
+        g.next();
+        assert_eq!(g.n_removed(), 1);
+        g.next();
+        assert_eq!(g.n_removed(), 0);
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0
+             0    0    0
+             0    0    0
+            0d   0d   0d
+        "#);
+    }
+
+    // Every cell that is spawned either stays on the grid or is removed
+    fn check_removed_cells_are_counted(mut g: Grid) {
+        let (w, h) = g.get_dims();
+        g.draw_drain(&Shape::Line {
+            x0: w / 3,
+            y0: h - 1,
+            x1: 2 * w / 3,
+            y1: h - 1,
+        });
+        let count = |g: &Grid| {
+            g.get_front()
+                .iter()
+                .filter(|c| c.material.is_movable())
+                .count()
+        };
+        let mut total_removed = 0;
+        for i in 0..300 {
+            let before = count(&g);
+            g.spawn(i);
+            let spawned = count(&g) - before;
+            g.next();
+            total_removed += g.n_removed();
+            assert_eq!(count(&g) + g.n_removed(), before + spawned);
+        }
+        assert!(total_removed > 0);
+    }
+
+    #[test]
+    fn removed_cells_are_counted() {
+        check_removed_cells_are_counted(Grid::new(
+            30,
+            20,
+            3,
+            0,
+            dummy_colour_map(),
+        ));
+        check_removed_cells_are_counted(margolus_grid(30, 20, 3));
+        // Drains in the blocks that wrap around the edges, on a row that is
+        // at the bottom of a block on frames where blocks are offset
+        for scheduler in [Scheduler::Ribbons, Scheduler::Margolus] {
+            let mut g = Grid::new(30, 20, 3, 0, dummy_colour_map());
+            g.set_boundaries(sides(Boundary::Periodic));
+            g.set_scheduler(scheduler);
+            for x in [0, 1, 28, 29] {
+                g.draw_drain(&Shape::Point { x, y: 18 });
+            }
+            check_removed_cells_are_counted(g);
+        }
+    }
+
+    fn boundary_scene(
+        boundaries: Boundaries,
+        scheduler: Scheduler,
+    ) -> (String, usize) {
+        let mut g = Grid::new(6, 4, 2, 0, dummy_colour_map());
+        g.set_boundaries(boundaries);
+        g.set_scheduler(scheduler);
+        for y in 0..4 {
+            g.set_px(0, y, y as u8 + 1);
+        }
+        g.set_cell(5, 3, water(5));
+        g.set_cell(4, 3, water(6));
+        let mut n_removed = 0;
+        for _ in 0..6 {
+            g.next();
+            n_removed += g.n_removed();
+        }
+        (g.to_string(), n_removed)
+    }
+
+    fn sides(boundary: Boundary) -> Boundaries {
+        Boundaries {
+            left: boundary,
+            right: boundary,
+            bottom: Boundary::Solid,
+        }
+    }
+
+    #[test]
+    fn solid_boundaries() {
+        let solid = Boundaries::default();
+        let (grid, n_removed) = boundary_scene(solid, Scheduler::Ribbons);
+        assert_eq!(n_removed, 0);
+        assert_snapshot!(grid, @r#"
+            0    0    0    0    0    0
+            0    0    0    0    0    0
+            3    0    0    0    0    0
+            4    2   6w    1   5w    0
+        "#);
+    }
+
+    #[test]
+    fn open_side_boundaries() {
+        let open = sides(Boundary::Open);
+        let (grid, n_removed) = boundary_scene(open, Scheduler::Ribbons);
+        assert_eq!(n_removed, 3);
+        assert_snapshot!(grid, @r#"
+            0    0    0    0    0    0
+            0    0    0    0    0    0
+            0    0    0    0    0    0
+            4    1    2    0    0    0
+        "#);
+    }
+
+    #[test]
+    fn open_bottom_boundary() {
+        let open = Boundaries {
+            bottom: Boundary::Open,
+            ..Boundaries::default()
+        };
+        let (grid, n_removed) = boundary_scene(open, Scheduler::Ribbons);
+        assert_eq!(n_removed, 6);
+        assert_snapshot!(grid, @r#"
+            0    0    0    0    0    0
+            0    0    0    0    0    0
+            0    0    0    0    0    0
+            0    0    0    0    0    0
+        "#);
+    }
+
+    #[test]
+    fn periodic_boundaries() {
+        let periodic = sides(Boundary::Periodic);
+        let (grid, n_removed) = boundary_scene(periodic, Scheduler::Ribbons);
+        assert_eq!(n_removed, 0);
+        assert_snapshot!(grid, @r#"
+            0    0    0    0    0    0
+            0    0    0    0    0    0
+            0    0    0    0    0    0
+            4    1    2   5w   6w    3
+        "#);
+    }
+
+    #[test]
+    fn margolus_open_side_boundaries() {
+        let open = sides(Boundary::Open);
+        let (grid, n_removed) = boundary_scene(open, Scheduler::Margolus);
+        assert_eq!(n_removed, 1);
+        assert_snapshot!(grid, @r#"
+            0    0    0    0    0    0
+            0    0    0    0    0    0
+            2    1    0    0    0    0
+            4    3    0   6w    0    0
+        "#);
+    }
+
+    #[test]
+    fn margolus_periodic_boundaries() {
+        let periodic = sides(Boundary::Periodic);
+        let (grid, n_removed) = boundary_scene(periodic, Scheduler::Margolus);
+        assert_eq!(n_removed, 0);
+        assert_snapshot!(grid, @r#"
+            0    0    0    0    0    0
+            0    0    0    0    0    0
+            2    1    0    0    0    0
+            4    3   6w   5w    0    0
+        "#);
+    }
+
+    // Grains crossing a periodic edge land in the chunk on the opposite
+    // side, which must be woken up for them to keep moving
+    #[test]
+    fn periodic_boundaries_keep_grains() {
+        let run = |n_threads| {
+            let mut g = Grid::new(100, 40, n_threads, 1, dummy_colour_map());
+            g.set_boundaries(sides(Boundary::Periodic));
+            g.set_spawners(vec![Spawner::new(Shape::Point { x: 0, y: 0 })]);
+            for i in 0..400 {
+                g.spawn(i);
+                g.next();
+            }
+            let n_grains = g.get_front().iter().filter(|c| !c.is_empty());
+            assert_eq!(n_grains.count(), 400);
+            g.get_front().clone()
+        };
+        let expected = run(1);
+        assert!(run(4) == expected);
+    }
+
+    fn margolus_grid(w: usize, h: usize, n_threads: usize) -> Grid {
+        let mut g = Grid::new(w, h, n_threads, 0, dummy_colour_map());
+        g.set_scheduler(Scheduler::Margolus);
+        g
+    }
+
+    #[test]
+    fn margolus_falls_straight() {
+        let mut g = margolus_grid(3, 4, 2);
+        g.set_px(1, 0, 2);
+        g.next();
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+            0    0    0
+            0    0    0
+            0    2    0
+            0    0    0
+        "#);
+        g.next();
+        g.next();
+        assert_snapshot!(g.to_string(), @r#"
+            0    0    0
+            0    0    0
+            0    0    0
+            0    2    0
+        "#);
+    }
+
+    #[test]
+    fn margolus_wall_blocks_falling_grains() {
+        let mut g = margolus_grid(4, 4, 2);
+        g.draw_wall(&Shape::Rect {
+            x0: 0,
+            y0: 2,
+            x1: 3,
+            y1: 2,
+        });
+        g.set_px(1, 0, 1);
+        g.set_px(2, 0, 2);
+        for _ in 0..4 {
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0
+             0    1    2    0
+            0#   0#   0#   0#
+             0    0    0    0
+        "#);
+    }
+
+    #[test]
+    fn margolus_sinks_through_liquid() {
+        let mut g = margolus_grid(4, 6, 3);
+        g.draw(
+            &Shape::Rect {
+                x0: 0,
+                y0: 3,
+                x1: 3,
+                y1: 5,
+            },
+            water(1),
+        );
+        g.set_px(1, 0, 2);
+        for _ in 0..12 {
+            g.next();
+        }
+        assert_snapshot!(g.to_string(), @r#"
+             0    0    0    0
+             0    0    0    0
+             0   1w    0    0
+            1w   1w   1w   1w
+            1w   1w   1w   1w
+            1w    2   1w   1w
+        "#);
+    }
+
+    #[test]
+    fn margolus_piles_are_symmetric() {
+        let (w, h) = (61, 32);
+        let mut g = margolus_grid(w, h, 3);
+        for _ in 0..400 {
+            g.set_px(w / 2, 0, 1);
+            g.next();
+        }
+        let front = g.get_front();
+        let count = |xs: std::ops::Range<usize>| {
+            xs.flat_map(|x| (0..h).map(move |y| y * w + x))
+                .filter(|&i| !front[i].is_empty())
+                .count()
+        };
+        let (left, right) = (count(0..w / 2), count(w / 2 + 1..w));
+        assert!(left > 0 && right > 0);
+        assert!(left.abs_diff(right) <= (left + right) / 10);
+    }
+
+    #[test]
+    fn margolus_reproducible_across_thread_counts() {
+        let run = |n_threads| {
+            let mut g = margolus_grid(37, 11, n_threads);
+            g.draw(&Shape::Circle { x: 20, y: 8, r: 3 }, water(4));
+            g.draw_wall(&Shape::Line {
+                x0: 0,
+                y0: 6,
+                x1: 10,
+                y1: 9,
+            });
+            for i in 0..60 {
+                g.spawn(i);
+                g.next();
+            }
+            g.get_front().clone()
+        };
+        let expected = run(1);
+        for n_threads in [2, 4, 8, 64] {
+            assert!(
+                run(n_threads) == expected,
+                "{} threads diverged from 1 thread",
+                n_threads
+            );
+        }
+    }
 }
@@ -0,0 +1,47 @@
+//! Falling sand simulation.
+//!
+//! A [`Grid`] holds a double-buffered field of [`Cell`]s and advances it one
+//! frame at a time with [`Grid::next`], splitting the work between a pool of
+//! threads. Grids are created with a [`GridBuilder`]. The result only depends
+//! on the seed, not on the number of threads.
+//!
+//! ```
+//! use sable::{Cell, GridBuilder, Material, Shape};
+//!
+//! let mut grid = GridBuilder::new(16, 8).threads(2).build().unwrap();
+//! grid.draw_wall(&Shape::Rect { x0: 0, y0: 7, x1: 15, y1: 7 });
+//! grid.set_cell(4, 0, Cell::new(Material::Sand, 1));
+//! for _ in 0..10 {
+//!     grid.next();
+//! }
+//! assert_eq!(grid.get_cell(4, 6), Cell::new(Material::Sand, 1));
+//! ```
+
+pub mod builder;
+mod chunks;
+pub mod colour;
+pub mod grid;
+mod lines;
+mod margolus;
+pub mod material;
+pub mod one_shot;
+mod pool;
+pub mod record;
+pub mod render;
+pub mod scene;
+pub mod shape;
+pub mod snapshot;
+pub mod spawner;
+pub mod stream;
+
+pub use builder::{BuildError, GridBuilder};
+pub use colour::{ColourMap, Gradient, NamedColourMap};
+pub use grid::{
+    Boundaries, Boundary, Config, Grid, Scheduler, DEFAULT_DISPERSION,
+};
+pub use material::{Cell, Material};
+pub use render::RenderMode;
+pub use scene::{Image, Palette, SceneError};
+pub use shape::Shape;
+pub use snapshot::{Snapshot, SnapshotError};
+pub use spawner::{ColourSchedule, Spawner};
@@ -0,0 +1,35 @@
+// Parses every line of a file that isn't blank or a comment starting with
+// `#`, with surrounding whitespace removed. Errors are prefixed with the
+// line number they occurred on
+pub(crate) fn parse_lines<'a, T>(
+    s: &'a str,
+    mut parse: impl FnMut(&'a str) -> Result<T, String> + 'a,
+) -> impl Iterator<Item = Result<T, String>> + 'a {
+    s.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(move |(i, line)| {
+            parse(line).map_err(|e| format!("Line {}: {}", i + 1, e))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_blank_and_comment_lines() {
+        let lines =
+            parse_lines("# a\n\n  b  \n\tc", Ok).collect::<Result<Vec<_>, _>>();
+        assert_eq!(lines, Ok(vec!["b", "c"]));
+        let err = parse_lines("1\n# 2\nx", |line| {
+            line.parse::<u8>().map_err(|e| e.to_string())
+        })
+        .collect::<Result<Vec<_>, _>>();
+        assert_eq!(
+            err,
+            Err(String::from("Line 3: invalid digit found in string"))
+        );
+    }
+}
@@ -1,10 +1,21 @@
-mod colour;
-mod grid;
-mod one_shot;
 mod pixels;
 mod softbuffer;
 
-use clap::{Args, Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use sable::record::{main_record, Recorder};
+use sable::stream::{main_stream, StreamFormat};
+use sable::{colour, grid, one_shot, shape, spawner};
+use sable::{Cell, Grid, GridBuilder, Image, Palette, RenderMode};
+use sable::{ColourMap, NamedColourMap, Snapshot, SnapshotError};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Width and height of the grid unless given otherwise
+const DEFAULT_SIZE: usize = 100;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None, propagate_version = true)]
@@ -12,40 +23,168 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    #[arg(long, default_value_t = 100)]
-    width: usize,
+    /// Defaults to 100, or to the width of `--scene-text`
+    #[arg(long)]
+    width: Option<usize>,
 
-    #[arg(long, default_value_t = 100)]
-    height: usize,
+    /// Defaults to 100, or to the height of `--scene-text`
+    #[arg(long)]
+    height: Option<usize>,
 
     #[arg(short = 't', long = "threads", default_value_t = 4)]
     n_threads: usize,
 
-    #[arg(long, group = "colour", default_value_t = true)]
-    rgb_continuous: bool,
+    /// Seed for the random number generator, or `random` to pick one. The
+    /// same seed gives the same result for any number of threads
+    #[arg(long, default_value = "0")]
+    seed: Seed,
+
+    /// Strategy used to split the work of each frame between threads
+    #[arg(long, value_enum, default_value_t = grid::Scheduler::Ribbons)]
+    scheduler: grid::Scheduler,
+
+    /// What happens to cells reaching the left edge. Periodic edges wrap
+    /// around, and must be used on both sides
+    #[arg(long, value_enum, default_value_t = grid::Boundary::Solid)]
+    left_boundary: grid::Boundary,
+
+    /// What happens to cells reaching the right edge
+    #[arg(long, value_enum, default_value_t = grid::Boundary::Solid)]
+    right_boundary: grid::Boundary,
+
+    /// What happens to cells reaching the bottom edge. Can't be periodic
+    #[arg(long, value_enum, default_value_t = grid::Boundary::Solid)]
+    bottom_boundary: grid::Boundary,
+
+    /// Number of cells a liquid can flow sideways in a single frame
+    #[arg(long, default_value_t = grid::DEFAULT_DISPERSION)]
+    dispersion: usize,
+
+    /// Number of grains dropped along the top row every frame. Defaults to
+    /// one for every 20 columns
+    #[arg(long, conflicts_with_all = ["spawners", "spawn_file"])]
+    spawn_rate: Option<usize>,
+
+    /// Add a spawner, as a shape followed by optional settings, for example
+    /// `point:50,0 rate=2 material=water colour=random`. Colours can be a
+    /// value, `cycle[:<period>]` or `random`. Replaces the default spawner
+    /// along the top row. Can be given multiple times
+    #[arg(long = "spawn", value_name = "SPAWNER")]
+    spawners: Vec<spawner::Spawner>,
+
+    /// Read spawners from a file, one per line in the same format as
+    /// `--spawn`. Lines starting with `#` are ignored
+    #[arg(long)]
+    spawn_file: Option<PathBuf>,
+
+    /// Start from an image, in PNG or BMP format, whose pixels are mapped to
+    /// cells with `--scene-palette`. Walls and drains are drawn on top
+    #[arg(long)]
+    scene: Option<PathBuf>,
+
+    /// Start from a text file in the format printed by `terminal`, with one
+    /// row per line and cells separated by whitespace. The grid takes the
+    /// dimensions of the scene. Walls and drains are drawn on top
+    #[arg(long, conflicts_with = "scene")]
+    scene_text: Option<PathBuf>,
+
+    /// Read the colours of `--scene` from a file, one per line as a hex RGB
+    /// value, a material and an optional colour value, for example
+    /// `c2b280 sand 40`. Black is empty, and walls and drains use the
+    /// colours they are rendered with, unless overridden
+    #[arg(long, requires = "scene")]
+    scene_palette: Option<PathBuf>,
+
+    /// Stretch `--scene` to `--width` and `--height` instead of requiring
+    /// the image to have the same dimensions
+    #[arg(long, requires = "scene")]
+    rescale_scene: bool,
+
+    /// Draw a wall, as one of `rect:x0,y0,x1,y1`, `line:x0,y0,x1,y1`, or
+    /// `circle:x,y,r`. Can be given multiple times
+    #[arg(long = "wall")]
+    walls: Vec<shape::Shape>,
+
+    /// Draw a drain that removes anything resting on it, in the same format
+    /// as `--wall`. Can be given multiple times
+    #[arg(long = "drain")]
+    drains: Vec<shape::Shape>,
 
-    #[arg(long, group = "colour")]
-    rgb_discrete: bool,
+    /// Built-in colour map used to render cells. Can't be combined with
+    /// `--palette`
+    #[arg(long, value_enum, default_value_t = NamedColourMap::Continuous)]
+    colour: NamedColourMap,
+
+    /// Colour map used to render cells, either one of the `--colour` maps
+    /// or a file listing hex RGB colours, one per line, that are blended
+    /// from the lowest colour value to the highest. Can't be combined with
+    /// `--colour`
+    #[arg(long, conflicts_with = "colour")]
+    palette: Option<PathBuf>,
+
+    /// What the colour of each cell is derived from. Every mode except
+    /// material goes through the colour map
+    #[arg(long, value_enum, default_value_t = RenderMode::Colour)]
+    render_mode: RenderMode,
+}
+
+#[derive(Clone, Copy)]
+enum Seed {
+    Fixed(u64),
+    Random,
+}
+
+impl FromStr for Seed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Seed::Random),
+            s => s.parse().map(Seed::Fixed).map_err(|_| {
+                format!("Expected an integer or 'random', got '{}'", s)
+            }),
+        }
+    }
+}
+
+fn get_seed(cli: &Cli) -> u64 {
+    match cli.seed {
+        Seed::Fixed(seed) => seed,
+        Seed::Random => {
+            let seed = rand::random();
+            // Printed to stderr to keep `terminal` output parseable
+            eprintln!("Using seed {}", seed);
+            seed
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
     Realtime(RealtimeArgs),
     Bmp(BmpArgs),
+    /// Run headless and save the final frame as a PNG file
+    Image(ImageArgs),
+    /// Run headless and save every Nth frame as an animated GIF or a
+    /// numbered sequence of PNG files
+    Record(RecordArgs),
+    /// Run headless and write every frame to stdout, for piping into a
+    /// video encoder such as ffmpeg
+    Stream(StreamArgs),
     Terminal(TerminalArgs),
 }
 
+/// Library used to draw frames in a window
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Frontend {
+    Pixels,
+    Softbuffer,
+}
+
 #[derive(Args)]
-#[group(required = false, multiple = false)]
 struct RealtimeArgs {
-    /// Use `pixels` frontend
-    #[arg(long)]
-    #[arg(default_value_t = true)]
-    pixels: bool,
-
-    /// Use `softbuffer` frontend
-    #[arg(long)]
-    softbuffer: bool,
+    #[arg(long, value_enum, default_value_t = Frontend::Pixels)]
+    frontend: Frontend,
 }
 
 #[derive(Args)]
@@ -55,51 +194,434 @@ struct BmpArgs {
 
     #[arg(short = 'i', long = "iterations")]
     n_iterations: usize,
+
+    #[command(flatten)]
+    snapshot: SnapshotArgs,
+}
+
+#[derive(Args)]
+struct ImageArgs {
+    #[arg(short, long, default_value_t = String::from("out.png"))]
+    output: String,
+
+    #[arg(short = 'i', long = "iterations")]
+    n_iterations: usize,
+
+    #[command(flatten)]
+    scale: ScaleArgs,
+
+    #[command(flatten)]
+    snapshot: SnapshotArgs,
+}
+
+#[derive(Args)]
+struct RecordArgs {
+    /// An animated GIF if the name ends in `.gif`. If it ends in `.png`,
+    /// every frame is written to its own file with the frame number
+    /// appended, such as `out_00042.png`
+    #[arg(short, long, default_value = "out.gif")]
+    output: PathBuf,
+
+    #[arg(short = 'i', long = "iterations")]
+    n_iterations: usize,
+
+    /// Record the first frame and then every Nth frame after it
+    #[arg(long, default_value_t = 1)]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    every: u32,
+
+    /// Time each frame of a GIF is shown for, in milliseconds. GIF delays
+    /// are rounded down to hundredths of a second
+    #[arg(long, default_value_t = 50)]
+    delay: u32,
+
+    #[command(flatten)]
+    scale: ScaleArgs,
+
+    #[command(flatten)]
+    snapshot: SnapshotArgs,
+}
+
+#[derive(Args)]
+struct StreamArgs {
+    #[arg(short = 'i', long = "iterations")]
+    n_iterations: usize,
+
+    #[arg(long, value_enum, default_value_t = StreamFormat::Rgba)]
+    format: StreamFormat,
+
+    /// Frame rate stored in Y4M streams
+    #[arg(long, default_value_t = 30)]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    fps: u32,
+
+    #[command(flatten)]
+    scale: ScaleArgs,
+
+    #[command(flatten)]
+    snapshot: SnapshotArgs,
 }
 
 #[derive(Args)]
 struct TerminalArgs {
     #[arg(short = 'i', long = "iterations")]
     n_iterations: usize,
+
+    #[command(flatten)]
+    snapshot: SnapshotArgs,
+}
+
+#[derive(Args)]
+struct SnapshotArgs {
+    /// Resume from a snapshot written with `--save`. Its dimensions and seed
+    /// replace `--width`, `--height` and `--seed`, and the scene, walls and
+    /// drains aren't drawn again
+    #[arg(long)]
+    load: Option<PathBuf>,
+
+    /// Write a snapshot of the final frame, which can be resumed with
+    /// `--load`
+    #[arg(long)]
+    save: Option<PathBuf>,
 }
 
-fn get_convert_colour(cli: &Cli) -> fn(f64) -> u32 {
-    if cli.rgb_continuous {
-        colour::hsv_to_rgb
-    } else if cli.rgb_discrete {
-        colour::discrete_rgb
+#[derive(Args)]
+struct ScaleArgs {
+    /// Draw every cell as a square of this many pixels
+    #[arg(long, default_value_t = 1)]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    scale: u32,
+}
+
+impl ScaleArgs {
+    fn get(&self) -> usize {
+        self.scale as usize
+    }
+}
+
+impl Commands {
+    fn snapshot_args(&self) -> Option<&SnapshotArgs> {
+        match self {
+            Commands::Realtime(_) => None,
+            Commands::Bmp(cmd) => Some(&cmd.snapshot),
+            Commands::Image(cmd) => Some(&cmd.snapshot),
+            Commands::Record(cmd) => Some(&cmd.snapshot),
+            Commands::Stream(cmd) => Some(&cmd.snapshot),
+            Commands::Terminal(cmd) => Some(&cmd.snapshot),
+        }
+    }
+}
+
+fn get_colour_map(cli: &Cli) -> Arc<dyn ColourMap> {
+    if let Some(palette) = &cli.palette {
+        let name = palette.to_str().unwrap_or_default();
+        if let Ok(named) = NamedColourMap::from_str(name, true) {
+            return named.colour_map();
+        }
+        let gradient = std::fs::read_to_string(palette)
+            .map_err(|e| e.to_string())
+            .and_then(|s| s.parse::<colour::Gradient>())
+            .unwrap_or_else(|e| {
+                usage_error(format!("Invalid palette {:?}: {}", palette, e))
+            });
+        Arc::new(gradient)
     } else {
-        panic!("Colour conversion function is not set")
+        cli.colour.colour_map()
+    }
+}
+
+fn get_spawners(cli: &Cli) -> Vec<spawner::Spawner> {
+    let mut spawners = cli.spawners.clone();
+    if let Some(path) = &cli.spawn_file {
+        let parsed = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| spawner::parse_spawners(&s))
+            .unwrap_or_else(|e| {
+                usage_error(format!("Invalid spawn file {:?}: {}", path, e))
+            });
+        spawners.extend(parsed);
+    }
+    spawners
+}
+
+fn get_scene(cli: &Cli, path: &Path, dims: (usize, usize)) -> Vec<Cell> {
+    let palette = match &cli.scene_palette {
+        Some(palette_path) => std::fs::read_to_string(palette_path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| s.parse())
+            .unwrap_or_else(|e| {
+                usage_error(format!(
+                    "Invalid palette {:?}: {}",
+                    palette_path, e
+                ))
+            }),
+        None => Palette::default(),
+    };
+    Image::open(path)
+        .map(|image| {
+            if cli.rescale_scene {
+                image.resize(dims.0, dims.1)
+            } else {
+                image
+            }
+        })
+        .and_then(|image| image.to_cells(&palette, dims))
+        .unwrap_or_else(|e| {
+            usage_error(format!("Invalid scene {:?}: {}", path, e))
+        })
+}
+
+// Text scenes carry their own dimensions, which only have to match
+// `--width` and `--height` when those are given
+fn check_text_scene_dims(
+    cli: &Cli,
+    (w, h): (usize, usize),
+) -> Result<(), String> {
+    let conflicts = cli.width.is_some_and(|width| width != w)
+        || cli.height.is_some_and(|height| height != h);
+    if conflicts {
+        let (width, height) = (cli.width.unwrap_or(w), cli.height.unwrap_or(h));
+        return Err(format!(
+            "Expected a {}x{} scene, got {}x{}",
+            width, height, w, h
+        ));
     }
+    Ok(())
+}
+
+fn get_text_scene(cli: &Cli, path: &Path) -> ((usize, usize), Vec<Cell>) {
+    std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| grid::parse_cells(&s))
+        .and_then(|(dims, cells)| {
+            check_text_scene_dims(cli, dims)?;
+            Ok((dims, cells))
+        })
+        .unwrap_or_else(|e| {
+            usage_error(format!("Invalid scene {:?}: {}", path, e))
+        })
+}
+
+fn usage_error(msg: impl std::fmt::Display) -> ! {
+    Cli::command().error(ErrorKind::ValueValidation, msg).exit()
+}
+
+fn io_error(path: &Path, e: impl std::fmt::Display) -> ! {
+    let msg = format!("{:?}: {}", path, e);
+    Cli::command().error(ErrorKind::Io, msg).exit()
+}
+
+fn load_snapshot(path: &Path) -> Snapshot {
+    File::open(path)
+        .map_err(SnapshotError::from)
+        .and_then(|f| Snapshot::read(BufReader::new(f)))
+        .unwrap_or_else(|e| io_error(path, e))
+}
+
+fn save_snapshot(g: &Grid, path: &Path) {
+    File::create(path)
+        .and_then(|f| g.save(BufWriter::new(f)))
+        .unwrap_or_else(|e| io_error(path, e))
 }
 
 fn main() {
     let cli = Cli::parse();
-    let convert_colour = get_convert_colour(&cli);
-    let mut g = grid::Grid::new(
-        cli.width,
-        cli.height,
-        cli.n_threads,
-        0,
-        convert_colour,
-    );
+    let snapshot_args = cli.command.snapshot_args();
+    let snapshot = snapshot_args
+        .and_then(|args| args.load.as_deref())
+        .map(load_snapshot);
+    // Scenes aren't drawn when resuming from a snapshot
+    let text_scene = match &snapshot {
+        Some(_) => None,
+        None => cli.scene_text.as_deref().map(|p| get_text_scene(&cli, p)),
+    };
+    let (width, height) = match (&snapshot, &text_scene) {
+        (Some(snapshot), _) => snapshot.get_dims(),
+        (None, Some((dims, _))) => *dims,
+        (None, None) => (
+            cli.width.unwrap_or(DEFAULT_SIZE),
+            cli.height.unwrap_or(DEFAULT_SIZE),
+        ),
+    };
+    let seed = match &snapshot {
+        Some(snapshot) => snapshot.seed(),
+        None => get_seed(&cli),
+    };
+    let colour_map = get_colour_map(&cli);
+    let mut builder = GridBuilder::new(width, height)
+        .threads(cli.n_threads)
+        .seed(seed)
+        .colour_map(colour_map)
+        .render_mode(cli.render_mode)
+        .scheduler(cli.scheduler)
+        .dispersion(cli.dispersion)
+        .boundaries(grid::Boundaries {
+            left: cli.left_boundary,
+            right: cli.right_boundary,
+            bottom: cli.bottom_boundary,
+        });
+    if let Some(rate) = cli.spawn_rate {
+        builder = builder.spawn_rate(rate);
+    }
+    for spawner in get_spawners(&cli) {
+        builder = builder.spawner(spawner);
+    }
+    let mut g = builder.build().unwrap_or_else(|e| usage_error(e));
+    if let Some(snapshot) = snapshot {
+        // The grid was built with the snapshot's dimensions
+        g.restore(snapshot).unwrap();
+    } else {
+        if let Some(path) = &cli.scene {
+            g.set_cells(&get_scene(&cli, path, g.get_dims()));
+        }
+        if let Some((_, cells)) = &text_scene {
+            g.set_cells(cells);
+        }
+        for wall in &cli.walls {
+            g.draw_wall(wall);
+        }
+        for drain in &cli.drains {
+            g.draw_drain(drain);
+        }
+    }
     match &cli.command {
-        Commands::Realtime(cmd) => {
-            if cmd.pixels {
+        Commands::Realtime(cmd) => match cmd.frontend {
+            Frontend::Pixels => {
                 println!("Using 'pixels' frontend");
                 pixels::main(&mut g)
-            } else if cmd.softbuffer {
+            }
+            Frontend::Softbuffer => {
                 println!("Using 'softbuffer' frontend");
                 softbuffer::main(&mut g)
-            } else {
-                panic!("Frontend is not set")
             }
-        }
+        },
         Commands::Bmp(cmd) => {
-            one_shot::main_bmp(&mut g, cmd.n_iterations, cmd.output.as_str())
+            one_shot::main_bmp(&mut g, cmd.n_iterations, &cmd.output)
+                .unwrap_or_else(|e| io_error(Path::new(&cmd.output), e))
+        }
+        Commands::Image(cmd) => {
+            let scale = cmd.scale.get();
+            one_shot::main_png(&mut g, cmd.n_iterations, &cmd.output, scale)
+                .unwrap_or_else(|e| io_error(Path::new(&cmd.output), e))
+        }
+        Commands::Record(cmd) => {
+            let path = cmd.output.as_path();
+            let (every, scale) = (cmd.every as usize, cmd.scale.get());
+            Recorder::new(path, g.get_dims(), scale, cmd.delay)
+                .and_then(|recorder| {
+                    main_record(&mut g, cmd.n_iterations, every, recorder)
+                })
+                .unwrap_or_else(|e| io_error(path, e))
+        }
+        Commands::Stream(cmd) => {
+            let out = BufWriter::new(std::io::stdout().lock());
+            let (fps, scale) = (cmd.fps, cmd.scale.get());
+            let n = cmd.n_iterations;
+            main_stream(&mut g, n, cmd.format, fps, scale, out).unwrap_or_else(
+                |e| {
+                    let msg = format!("Writing to stdout failed: {}", e);
+                    Cli::command().error(ErrorKind::Io, msg).exit()
+                },
+            )
         }
         Commands::Terminal(cmd) => {
             one_shot::main_terminal(&mut g, cmd.n_iterations)
         }
     };
+    if let Some(path) = snapshot_args.and_then(|args| args.save.as_deref()) {
+        save_snapshot(&g, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(["sable"].iter().chain(args))
+    }
+
+    fn parse_realtime(args: &[&str]) -> (NamedColourMap, Frontend) {
+        let cli = parse(args).unwrap();
+        match cli.command {
+            Commands::Realtime(cmd) => (cli.colour, cmd.frontend),
+            _ => panic!("Expected the realtime subcommand"),
+        }
+    }
+
+    #[test]
+    fn cli_is_consistent() {
+        Cli::command().debug_assert()
+    }
+
+    #[test]
+    fn parses_colours_and_frontends() {
+        assert_eq!(
+            parse_realtime(&["realtime"]),
+            (NamedColourMap::Continuous, Frontend::Pixels)
+        );
+        for colour in NamedColourMap::value_variants() {
+            for frontend in Frontend::value_variants() {
+                let colour_name = colour.to_possible_value().unwrap();
+                let frontend_name = frontend.to_possible_value().unwrap();
+                let args = [
+                    "--colour",
+                    colour_name.get_name(),
+                    "realtime",
+                    "--frontend",
+                    frontend_name.get_name(),
+                ];
+                assert_eq!(parse_realtime(&args), (*colour, *frontend));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_colours_and_frontends() {
+        let kind = |args: &[&str]| parse(args).err().map(|e| e.kind());
+        let invalid = Some(ErrorKind::InvalidValue);
+        assert_eq!(kind(&["--colour", "pink", "realtime"]), invalid);
+        assert_eq!(kind(&["realtime", "--frontend", "opengl"]), invalid);
+        assert_eq!(
+            kind(&["--colour", "magma", "--palette", "x.txt", "realtime"]),
+            Some(ErrorKind::ArgumentConflict)
+        );
+        // The flags they replace are gone
+        for flag in ["--rgb-continuous", "--rgb-discrete"] {
+            let err = kind(&[flag, "realtime"]);
+            assert_eq!(err, Some(ErrorKind::UnknownArgument));
+        }
+        for flag in ["--pixels", "--softbuffer"] {
+            let err = kind(&["realtime", flag]);
+            assert_eq!(err, Some(ErrorKind::UnknownArgument));
+        }
+        let cli = parse(&["--palette", "fire.txt", "realtime"]).unwrap();
+        assert_eq!(cli.palette, Some(PathBuf::from("fire.txt")));
+        assert_eq!(cli.colour, NamedColourMap::Continuous);
+    }
+
+    #[test]
+    fn palettes_can_be_named() {
+        let cli = parse(&["--palette", "Magma", "realtime"]).unwrap();
+        let magma = NamedColourMap::Magma.colour_map();
+        for v in [0.0, 100.0, 255.0] {
+            assert_eq!(get_colour_map(&cli).convert(v), magma.convert(v));
+        }
+    }
+
+    #[test]
+    fn text_scenes_set_the_dimensions() {
+        let check = |args: &[&str]| {
+            let mut args = args.to_vec();
+            args.extend(["terminal", "-i", "1"]);
+            check_text_scene_dims(&parse(&args).unwrap(), (3, 2))
+        };
+        assert_eq!(check(&[]), Ok(()));
+        assert_eq!(check(&["--width", "3", "--height", "2"]), Ok(()));
+        assert_eq!(check(&["--height", "2"]), Ok(()));
+        assert_eq!(
+            check(&["--width", "5"]),
+            Err(String::from("Expected a 5x2 scene, got 3x2"))
+        );
+    }
 }
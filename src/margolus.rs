@@ -0,0 +1,252 @@
+use crate::grid::{Boundaries, Boundary};
+use crate::material::{Cell, Material};
+use crate::pool::{Task, ThreadPool};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Cells past a solid edge act as walls, so partial blocks at the edges
+// behave like the solid boundaries of the ribbon scheduler. Cells past an
+// open edge are empty, and anything that moves into them is removed
+const OUTSIDE: Cell = Cell {
+    material: Material::Wall,
+    colour: 0,
+};
+
+fn outside(boundary: Boundary) -> Cell {
+    match boundary {
+        Boundary::Open => Cell::EMPTY,
+        _ => OUTSIDE,
+    }
+}
+
+// Indices of the cells in a block
+const TOP_LEFT: usize = 0;
+const TOP_RIGHT: usize = 1;
+const BOTTOM_LEFT: usize = 2;
+const BOTTOM_RIGHT: usize = 3;
+
+// SplitMix64, used to cheaply derive independent random bits for each block
+// from its position, so the result doesn't depend on how blocks are split
+// between threads
+fn block_hash(seed: u64, bx: usize, by: usize) -> u64 {
+    let mut z = seed
+        ^ (bx as u64).wrapping_mul(0x9E3779B97F4A7C15)
+        ^ (by as u64).wrapping_mul(0xC2B2AE3D27D4EB4F);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+// Whether the cell at `from` can move into `to`, either because it is empty
+// or by swapping places with a lighter material
+fn can_sink(block: &[Cell; 4], from: usize, to: usize) -> bool {
+    let (a, b) = (block[from].material, block[to].material);
+    a.is_movable()
+        && (b == Material::Empty || b.is_movable())
+        && b.density() < a.density()
+}
+
+fn fall(block: &mut [Cell; 4], top: usize, bottom: usize) -> bool {
+    let sinks = can_sink(block, top, bottom);
+    if sinks {
+        block.swap(top, bottom);
+    }
+    sinks
+}
+
+// Only granular materials and liquids slide diagonally
+fn slide(block: &mut [Cell; 4], top: usize, bottom: usize) -> bool {
+    let slides =
+        matches!(block[top].material, Material::Sand | Material::Water)
+            && can_sink(block, top, bottom);
+    if slides {
+        block.swap(top, bottom);
+    }
+    slides
+}
+
+// Removes the top cell if it is resting on a drain
+fn drain(block: &mut [Cell; 4], top: usize, bottom: usize) -> usize {
+    let drains = block[top].material.is_movable()
+        && block[bottom].material == Material::Drain;
+    if drains {
+        block[top] = Cell::EMPTY;
+    }
+    drains as usize
+}
+
+// Liquids flow sideways into empty cells
+fn flow(block: &mut [Cell; 4], left: usize, right: usize) {
+    let is_liquid = |i: usize| block[i].material == Material::Water;
+    let is_empty = |i: usize| block[i].is_empty();
+    if (is_liquid(left) && is_empty(right))
+        || (is_liquid(right) && is_empty(left))
+    {
+        block.swap(left, right);
+    }
+}
+
+// Updates a 2x2 block in isolation, which is what allows every block in a
+// frame to be processed in parallel without any synchronisation. Returns the
+// number of cells removed
+fn next_block(block: &mut [Cell; 4], rand: u64) -> usize {
+    let n_removed = drain(block, TOP_LEFT, BOTTOM_LEFT)
+        + drain(block, TOP_RIGHT, BOTTOM_RIGHT);
+    let left_moved = fall(block, TOP_LEFT, BOTTOM_LEFT);
+    let right_moved = fall(block, TOP_RIGHT, BOTTOM_RIGHT);
+    if !left_moved && !right_moved {
+        // Randomise which diagonal is tried first, so piles stay symmetric
+        let mut diagonals =
+            [(TOP_LEFT, BOTTOM_RIGHT), (TOP_RIGHT, BOTTOM_LEFT)];
+        if rand & 1 == 1 {
+            diagonals.swap(0, 1);
+        }
+        for (top, bottom) in diagonals {
+            if slide(block, top, bottom) {
+                break;
+            }
+        }
+    }
+    // Only flow some of the time, otherwise a liquid would just oscillate
+    // between the two cells of the same block
+    if rand & 2 == 0 {
+        flow(block, BOTTOM_LEFT, BOTTOM_RIGHT);
+    }
+    // The top row only flows when resting on top of something
+    let supported =
+        !block[BOTTOM_LEFT].is_empty() && !block[BOTTOM_RIGHT].is_empty();
+    if rand & 4 == 0 && supported {
+        flow(block, TOP_LEFT, TOP_RIGHT);
+    }
+    n_removed
+}
+
+// Splits each row of the target buffer into one slice per strip of columns
+fn generate_target_strips<'a>(
+    target: &'a mut [Cell],
+    width: usize,
+    strips: &[Range<usize>],
+) -> Vec<Vec<&'a mut [Cell]>> {
+    let mut rows = strips.iter().map(|_| vec![]).collect::<Vec<_>>();
+    for mut row in target.chunks_mut(width) {
+        let mut start = 0;
+        for (i, strip) in strips.iter().enumerate() {
+            let (slice, rest) = row.split_at_mut(strip.end - start);
+            rows[i].push(slice);
+            row = rest;
+            start = strip.end;
+        }
+    }
+    rows
+}
+
+// Splits the block columns into contiguous strips of grid columns, one per
+// thread, whose sizes differ by at most one block
+fn column_strips(
+    width: usize,
+    x_offset: usize,
+    n_threads: usize,
+) -> Vec<Range<usize>> {
+    let n_block_cols = (width + x_offset).div_ceil(2);
+    let n_strips = n_threads.min(n_block_cols);
+    let to_x = |bx: usize| (2 * bx).saturating_sub(x_offset).min(width);
+    (0..n_strips)
+        .map(|i| {
+            let start = i * n_block_cols / n_strips;
+            let end = (i + 1) * n_block_cols / n_strips;
+            to_x(start)..to_x(end)
+        })
+        .collect()
+}
+
+// Advances the grid by one frame with a Margolus neighbourhood: the grid is
+// cut into 2x2 blocks, offset by one cell diagonally on every other frame so
+// that cells can move between blocks. Each thread updates a vertical strip
+// of blocks, which scales with the width rather than the height of the grid.
+// Periodic edges require an even width, so that blocks line up across the
+// edge. Returns the number of cells removed
+pub fn propagate(
+    source: &[Cell],
+    target: &mut [Cell],
+    (width, height): (usize, usize),
+    boundaries: Boundaries,
+    pool: &ThreadPool,
+    frame: usize,
+    seed: u64,
+) -> usize {
+    let offset = frame % 2;
+    let strips = column_strips(width, offset, pool.size());
+    let target_strips = generate_target_strips(target, width, &strips);
+    let (w, h) = (width as isize, height as isize);
+    let wrap_x = |x: isize| {
+        if boundaries.is_periodic() {
+            x.rem_euclid(w)
+        } else {
+            x
+        }
+    };
+    let get = |x: isize, y: isize| {
+        let x = wrap_x(x);
+        if y < 0 {
+            OUTSIDE
+        } else if y >= h {
+            outside(boundaries.bottom)
+        } else if x < 0 {
+            outside(boundaries.left)
+        } else if x >= w {
+            outside(boundaries.right)
+        } else {
+            source[y as usize * width + x as usize]
+        }
+    };
+    let n_removed = &AtomicUsize::new(0);
+    let mut tasks: Vec<Task> = vec![];
+    for (strip, mut rows) in strips.iter().zip(target_strips) {
+        let x_range = strip.clone();
+        tasks.push(Box::new(move || {
+            let mut removed = 0;
+            let y_start = -(offset as isize);
+            let x_start = x_range.start as isize
+                - ((x_range.start + offset) % 2) as isize;
+            for by in (y_start..height as isize).step_by(2) {
+                for bx in (x_start..x_range.end as isize).step_by(2) {
+                    let coords = [
+                        (bx, by),
+                        (bx + 1, by),
+                        (bx, by + 1),
+                        (bx + 1, by + 1),
+                    ];
+                    let mut block = coords.map(|(x, y)| get(x, y));
+                    let bi = (wrap_x(bx) + 1) as usize / 2;
+                    let bj = (by + 1) as usize / 2;
+                    let mut block_removed =
+                        next_block(&mut block, block_hash(seed, bi, bj));
+                    for (&(x, y), cell) in coords.iter().zip(block) {
+                        let x = wrap_x(x);
+                        if x < 0 || x >= w || y >= h {
+                            // Only past an open edge can this be a cell
+                            // that moved out of the grid
+                            block_removed +=
+                                cell.material.is_movable() as usize;
+                            continue;
+                        }
+                        let in_strip = x >= x_range.start as isize
+                            && x < x_range.end as isize;
+                        if in_strip && y >= 0 {
+                            rows[y as usize][x as usize - x_range.start] = cell;
+                        }
+                    }
+                    // With periodic edges the block that wraps around on
+                    // offset frames is also processed as bx = -1, which
+                    // counts its removals
+                    if !(boundaries.is_periodic() && bx == w - 1) {
+                        removed += block_removed;
+                    }
+                }
+            }
+            n_removed.fetch_add(removed, Ordering::Relaxed);
+        }));
+    }
+    pool.run(tasks);
+    n_removed.load(Ordering::Relaxed)
+}
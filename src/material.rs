@@ -0,0 +1,144 @@
+/// What a cell is made of, which decides how it moves
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Material {
+    #[default]
+    Empty,
+    Sand,
+    Water,
+    Stone,
+    Wall,
+    /// Static cell that removes anything resting on top of it
+    Drain,
+}
+
+impl Material {
+    pub const ALL: [Material; 6] = [
+        Material::Empty,
+        Material::Sand,
+        Material::Water,
+        Material::Stone,
+        Material::Wall,
+        Material::Drain,
+    ];
+
+    /// Suffix used after the colour value in the text representation of a
+    /// cell. Sand has none so that plain sand grids print as bare integers
+    pub fn symbol(self) -> Option<char> {
+        match self {
+            Material::Empty | Material::Sand => None,
+            Material::Water => Some('w'),
+            Material::Stone => Some('s'),
+            Material::Wall => Some('#'),
+            Material::Drain => Some('d'),
+        }
+    }
+
+    /// Heavier materials sink through lighter ones by swapping places
+    pub fn density(self) -> u8 {
+        match self {
+            Material::Empty => 0,
+            Material::Water => 1,
+            Material::Sand => 2,
+            Material::Stone => 3,
+            Material::Wall | Material::Drain => u8::MAX,
+        }
+    }
+
+    /// Whether cells of this material can ever change position
+    pub fn is_movable(self) -> bool {
+        !matches!(self, Material::Empty | Material::Wall | Material::Drain)
+    }
+}
+
+// Parses the lowercase name of a material
+impl std::str::FromStr for Material {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "empty" => Ok(Material::Empty),
+            "sand" => Ok(Material::Sand),
+            "water" => Ok(Material::Water),
+            "stone" => Ok(Material::Stone),
+            "wall" => Ok(Material::Wall),
+            "drain" => Ok(Material::Drain),
+            _ => Err(format!("Unknown material '{}'", s)),
+        }
+    }
+}
+
+// Converts back from the `repr(u8)` discriminant, returning the byte if it
+// doesn't match any material
+impl TryFrom<u8> for Material {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Material::ALL
+            .into_iter()
+            .find(|&m| m as u8 == byte)
+            .ok_or(byte)
+    }
+}
+
+/// A single cell of the grid. The colour value is mapped to RGBA by the
+/// grid's colour map when rendering
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Cell {
+    pub material: Material,
+    pub colour: u8,
+}
+
+impl Cell {
+    pub const EMPTY: Cell = Cell {
+        material: Material::Empty,
+        colour: 0,
+    };
+
+    pub fn new(material: Material, colour: u8) -> Cell {
+        Cell { material, colour }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.material == Material::Empty
+    }
+}
+
+impl std::fmt::Display for Cell {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self.material.symbol() {
+            _ if self.is_empty() => String::from("0"),
+            None => self.colour.to_string(),
+            Some(c) => format!("{}{}", self.colour, c),
+        };
+        // Use `pad` so that width and alignment flags are respected
+        fmt.pad(&s)
+    }
+}
+
+// Parses the text representation of a cell, a colour value followed by the
+// material's symbol. A bare `0` is empty, as sand with colour 0 prints the
+// same way
+impl std::str::FromStr for Cell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (colour, material) = match s.chars().last() {
+            Some(c) if !c.is_ascii_digit() => {
+                let material = Material::ALL
+                    .into_iter()
+                    .find(|m| m.symbol() == Some(c))
+                    .ok_or(format!("Unknown material symbol '{}'", c))?;
+                (&s[..s.len() - c.len_utf8()], material)
+            }
+            _ => (s, Material::Sand),
+        };
+        let colour = colour
+            .parse()
+            .map_err(|_| format!("Invalid cell '{}'", s))?;
+        match (material, colour) {
+            (Material::Sand, 0) => Ok(Cell::EMPTY),
+            (material, colour) => Ok(Cell::new(material, colour)),
+        }
+    }
+}
@@ -1,45 +1,214 @@
+use crate::colour::to_rgba;
 use crate::grid::Grid;
-use bmp_rust::bmp::BMP;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 
-fn write_to_bmp(grid: &Grid, filename: &str) {
+// Lengths of the BITMAPFILEHEADER and BITMAPINFOHEADER structures that
+// start every BMP file
+const BMP_FILE_HEADER_LEN: u32 = 14;
+const BMP_INFO_HEADER_LEN: u32 = 40;
+
+/// Writes the current frame of the grid as a 32-bit BMP image. Rows are
+/// written bottom up, which is what a positive height means in the header.
+/// Empty cells are black
+pub fn write_bmp(grid: &Grid, mut out: impl Write) -> io::Result<()> {
     let (w, h) = grid.get_dims();
-    let mut bmp = BMP::new(h as i32, w as u32, None);
-    let dib_header = bmp.get_dib_header().unwrap();
-    let header = bmp.get_header();
-    let buf = grid.get_front();
-    for y in 0..h {
-        for x in 0..w {
-            let v = buf[y * w + x];
-            let colour = if v == 0 {
-                0
-            } else {
-                grid.convert_colour(v as f64)
-            };
-            bmp.change_color_of_pixel_efficient(
-                x as u16,
-                y as u16,
-                colour.to_ne_bytes(),
-                &dib_header,
-                &header,
-            )
-            .unwrap()
+    let too_large = || {
+        let msg = format!("A {}x{} grid is too large for a BMP file", w, h);
+        io::Error::new(io::ErrorKind::InvalidInput, msg)
+    };
+    let width = i32::try_from(w).map_err(|_| too_large())?;
+    let height = i32::try_from(h).map_err(|_| too_large())?;
+    // Rows of 32-bit pixels never need padding
+    let row_len = w.checked_mul(4).ok_or_else(too_large)?;
+    let offset = BMP_FILE_HEADER_LEN + BMP_INFO_HEADER_LEN;
+    let image_len = row_len
+        .checked_mul(h)
+        .and_then(|len| u32::try_from(len).ok())
+        .filter(|len| len.checked_add(offset).is_some())
+        .ok_or_else(too_large)?;
+
+    out.write_all(b"BM")?;
+    out.write_all(&(offset + image_len).to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&offset.to_le_bytes())?;
+
+    out.write_all(&BMP_INFO_HEADER_LEN.to_le_bytes())?;
+    out.write_all(&width.to_le_bytes())?;
+    out.write_all(&height.to_le_bytes())?;
+    // One plane, 32 bits per pixel
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&32u16.to_le_bytes())?;
+    // Uncompressed
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&image_len.to_le_bytes())?;
+    // 72 DPI, in pixels per metre
+    out.write_all(&2835i32.to_le_bytes())?;
+    out.write_all(&2835i32.to_le_bytes())?;
+    // No colour table
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+
+    let mut row = vec![0; row_len];
+    for colours in grid.render().chunks(w).rev() {
+        for (pixel, colour) in row.chunks_exact_mut(4).zip(colours) {
+            let [r, g, b, a] = to_rgba(*colour);
+            pixel.copy_from_slice(&[b, g, r, a]);
         }
+        out.write_all(&row)?;
     }
-    bmp.save_to_new(filename).unwrap()
+    out.flush()
 }
 
-pub fn main_terminal(grid: &mut Grid, n_iterations: usize) {
-    for i in 0..n_iterations {
-        grid.spawn(i as u32);
-        grid.next()
+/// Renders the current frame of the grid to a BMP file, see [`write_bmp`]
+pub fn write_to_bmp(grid: &Grid, filename: impl AsRef<Path>) -> io::Result<()> {
+    write_bmp(grid, BufWriter::new(File::create(filename)?))
+}
+
+/// Renders the current frame as RGBA bytes, row by row from the top, with
+/// every cell drawn as a `scale` by `scale` square. Empty cells are opaque
+/// black, as in BMP files
+pub fn render_rgba(grid: &Grid, scale: usize) -> Vec<u8> {
+    let (w, _) = grid.get_dims();
+    let colours = grid.render();
+    let mut out = Vec::with_capacity(colours.len() * scale * scale * 4);
+    for row in colours.chunks(w) {
+        let pixels = row
+            .iter()
+            .map(|&colour| to_rgba(colour))
+            .flat_map(|pixel| std::iter::repeat_n(pixel, scale))
+            .flatten()
+            .collect::<Vec<_>>();
+        for _ in 0..scale {
+            out.extend_from_slice(&pixels)
+        }
     }
-    println!("{}", grid)
+    out
+}
+
+/// Renders the current frame to a PNG file, scaled up by an integer factor
+pub fn write_to_png(
+    grid: &Grid,
+    filename: impl AsRef<Path>,
+    scale: usize,
+) -> io::Result<()> {
+    let (w, h) = grid.get_dims();
+    let too_large = || io::Error::other("Scaled image is too large");
+    let width = u32::try_from(w * scale).map_err(|_| too_large())?;
+    let height = u32::try_from(h * scale).map_err(|_| too_large())?;
+    let file = BufWriter::new(File::create(filename)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&render_rgba(grid, scale))?;
+    Ok(writer.finish()?)
 }
 
-pub fn main_bmp(grid: &mut Grid, n_iterations: usize, filename: &str) {
-    for i in 0..n_iterations {
-        grid.spawn(i as u32);
+/// Runs the simulation for `n_iterations` more frames, spawning sand every
+/// frame
+pub fn run(grid: &mut Grid, n_iterations: usize) {
+    for _ in 0..n_iterations {
+        grid.spawn(grid.frame() as u32);
         grid.next()
     }
+}
+
+/// Runs the simulation and prints the final frame as text
+pub fn main_terminal(grid: &mut Grid, n_iterations: usize) {
+    run(grid, n_iterations);
+    println!("{}", grid)
+}
+
+/// Runs the simulation and saves the final frame as a BMP file
+pub fn main_bmp(
+    grid: &mut Grid,
+    n_iterations: usize,
+    filename: &str,
+) -> io::Result<()> {
+    run(grid, n_iterations);
     write_to_bmp(grid, filename)
 }
+
+/// Runs the simulation and saves the final frame as a PNG file
+pub fn main_png(
+    grid: &mut Grid,
+    n_iterations: usize,
+    filename: &str,
+    scale: usize,
+) -> io::Result<()> {
+    run(grid, n_iterations);
+    write_to_png(grid, filename, scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_golden_bmp() {
+        let g: Grid = "
+              7    0   3w
+             0#  200s  0d
+        "
+        .parse()
+        .unwrap();
+        let mut bytes = vec![];
+        write_bmp(&g, &mut bytes).unwrap();
+        assert_eq!(bytes, include_bytes!("../testdata/frame.bmp"));
+    }
+
+    #[test]
+    fn bmp_reads_back_as_scene() {
+        let g: Grid = "
+             0#   40    0
+            170w   0   0d
+        "
+        .parse()
+        .unwrap();
+        let path = std::env::temp_dir()
+            .join(format!("sable-bmp-scene-{}.bmp", std::process::id()));
+        write_to_bmp(&g, &path).unwrap();
+        let image = crate::Image::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // Colours that aren't grey, so that swapping red and blue shows
+        let (sand, water) = (g.get_cell(1, 0), g.get_cell(0, 1));
+        let palette = crate::Palette::default()
+            .insert(g.cell_colour(sand) & 0xFFFFFF, sand)
+            .insert(g.cell_colour(water) & 0xFFFFFF, water);
+        assert_eq!(&image.to_cells(&palette, (3, 2)).unwrap(), g.get_front());
+    }
+
+    #[test]
+    fn writes_wide_bmp() {
+        // Wider than the u16 coordinates the previous writer was limited to
+        let (w, h) = (70_000, 2);
+        let mut g = crate::GridBuilder::new(w, h).build().unwrap();
+        g.draw_wall(&crate::Shape::Point { x: w - 1, y: 0 });
+        let mut bytes = vec![];
+        write_bmp(&g, &mut bytes).unwrap();
+        let int =
+            |i: usize| i32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        assert_eq!((int(18), int(22)), (w as i32, h as i32));
+        // Rows are stored bottom up, so the wall ends the last row
+        let row_len = w * 4;
+        assert_eq!(bytes.len(), 54 + h * row_len);
+        assert_eq!(bytes[bytes.len() - 4..], [128, 128, 128, 255]);
+        assert_eq!(bytes[54 + row_len - 4..54 + row_len], [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn renders_scaled_frames() {
+        let g: Grid = "0 0#\n0d 0".parse().unwrap();
+        let black = [0, 0, 0, 255];
+        let wall = [128, 128, 128, 255];
+        let drain = [48, 48, 48, 255];
+        assert_eq!(render_rgba(&g, 1), [black, wall, drain, black].concat());
+        let top = [black, black, wall, wall].concat();
+        let bottom = [drain, drain, black, black].concat();
+        assert_eq!(
+            render_rgba(&g, 2),
+            [top.clone(), top, bottom.clone(), bottom].concat()
+        );
+    }
+}
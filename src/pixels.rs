@@ -5,22 +5,14 @@ use winit::event::{Event, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{Window, WindowBuilder};
 
-use crate::grid::Grid;
+use sable::colour::to_rgba;
+use sable::Grid;
 
 fn get_dims(window: &Rc<Window>) -> (u32, u32) {
     let size = window.inner_size();
     (size.width, size.height)
 }
 
-fn render(grid: &Grid, i: usize, source: &[u8], target: &mut [u8]) {
-    let v = source[i];
-    let v = match v {
-        0 => 0,
-        v => grid.convert_colour(v as f64),
-    };
-    target.copy_from_slice(&v.to_ne_bytes());
-}
-
 fn handle_redraw_request(
     window: &Rc<Window>,
     pixels: &mut Pixels,
@@ -30,9 +22,9 @@ fn handle_redraw_request(
     let (width, height) = get_dims(window);
     pixels.resize_surface(width, height).unwrap();
     let target = pixels.frame_mut();
-    let source = grid.get_front();
-    for (i, pixel) in target.chunks_exact_mut(4).enumerate() {
-        render(grid, i, source, pixel)
+    let colours = grid.render();
+    for (pixel, colour) in target.chunks_exact_mut(4).zip(colours) {
+        pixel.copy_from_slice(&to_rgba(colour))
     }
     pixels.render().unwrap();
     grid.spawn(frame);
@@ -0,0 +1,122 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread::{self, JoinHandle};
+
+pub type Task<'a> = Box<dyn FnOnce() + Send + 'a>;
+
+struct Shared {
+    // One slot per worker, filled before each phase
+    slots: Vec<Mutex<Option<Task<'static>>>>,
+    // Workers wait on `start` before a phase and on `end` after it, along
+    // with the owning thread
+    start: Barrier,
+    end: Barrier,
+    panicked: AtomicBool,
+    shutdown: AtomicBool,
+}
+
+// Long-lived worker threads that run a batch of tasks per phase, avoiding
+// spawning new OS threads every time a frame is processed
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+fn worker(shared: Arc<Shared>, i: usize) {
+    loop {
+        shared.start.wait();
+        if shared.shutdown.load(Ordering::Acquire) {
+            break;
+        }
+        let task = shared.slots[i].lock().unwrap().take();
+        if let Some(task) = task {
+            if catch_unwind(AssertUnwindSafe(task)).is_err() {
+                shared.panicked.store(true, Ordering::Release);
+            }
+        }
+        shared.end.wait();
+    }
+}
+
+impl ThreadPool {
+    pub fn new(n_threads: usize) -> ThreadPool {
+        let shared = Arc::new(Shared {
+            slots: (0..n_threads).map(|_| Mutex::new(None)).collect(),
+            start: Barrier::new(n_threads + 1),
+            end: Barrier::new(n_threads + 1),
+            panicked: AtomicBool::new(false),
+            shutdown: AtomicBool::new(false),
+        });
+        let workers = (0..n_threads)
+            .map(|i| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || worker(shared, i))
+            })
+            .collect();
+        ThreadPool { shared, workers }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    // Runs each task on its own worker and blocks until all of them have
+    // finished, so tasks can borrow from the caller's stack like with
+    // `crossbeam::scope`. Panics if there are more tasks than workers, or
+    // if any of the tasks panicked
+    pub fn run<'a>(&self, tasks: Vec<Task<'a>>) {
+        assert!(tasks.len() <= self.size(), "More tasks than workers");
+        for (slot, task) in self.shared.slots.iter().zip(tasks) {
+            // SAFETY: the task is only run between the two barriers below,
+            // and this function doesn't return until every worker has
+            // reached the second one, so nothing borrowed by the task can
+            // be dropped while it is running
+            let task =
+                unsafe { std::mem::transmute::<Task<'a>, Task<'static>>(task) };
+            *slot.lock().unwrap() = Some(task);
+        }
+        self.shared.start.wait();
+        self.shared.end.wait();
+        if self.shared.panicked.swap(false, Ordering::AcqRel) {
+            panic!("Worker thread panicked")
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+        self.shared.start.wait();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_tasks_borrowing_from_caller() {
+        let pool = ThreadPool::new(3);
+        let mut values = vec![0; 3];
+        for round in 1..=2 {
+            let tasks = values
+                .iter_mut()
+                .enumerate()
+                .map(|(i, v)| Box::new(move || *v += i * round) as Task)
+                .collect();
+            pool.run(tasks);
+        }
+        assert_eq!(values, vec![0, 3, 6]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Worker thread panicked")]
+    fn propagates_panics() {
+        let pool = ThreadPool::new(2);
+        pool.run(vec![Box::new(|| panic!("Task failed"))]);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::grid::Grid;
+use crate::one_shot::{self, render_rgba};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+// Where recorded frames go, decided by the extension of the output path
+enum Output {
+    Gif(gif::Encoder<BufWriter<File>>),
+    // Path that frame numbers are inserted into
+    Png(PathBuf),
+}
+
+/// Writes frames of a headless run to an animated GIF, or to a numbered
+/// sequence of PNG files
+pub struct Recorder {
+    output: Output,
+    scale: usize,
+    // Time each GIF frame is shown for, in hundredths of a second
+    delay: u16,
+}
+
+fn gif_error(e: gif::EncodingError) -> io::Error {
+    io::Error::other(e)
+}
+
+impl Recorder {
+    /// Starts an animated GIF if the path ends in `.gif`, or a PNG sequence
+    /// if it ends in `.png`, see [`Recorder::frame_path`]. Every cell is
+    /// drawn as a `scale` by `scale` square, and GIF frames are shown for
+    /// `delay_ms` milliseconds, rounded down to hundredths of a second
+    pub fn new(
+        path: &Path,
+        dims: (usize, usize),
+        scale: usize,
+        delay_ms: u32,
+    ) -> io::Result<Recorder> {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let output = match extension.as_str() {
+            "gif" => {
+                let too_large = |_| io::Error::other("Animation is too large");
+                let width = u16::try_from(dims.0 * scale).map_err(too_large)?;
+                let height =
+                    u16::try_from(dims.1 * scale).map_err(too_large)?;
+                let file = BufWriter::new(File::create(path)?);
+                let mut encoder = gif::Encoder::new(file, width, height, &[])
+                    .map_err(gif_error)?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(gif_error)?;
+                Output::Gif(encoder)
+            }
+            "png" => Output::Png(path.to_path_buf()),
+            _ => {
+                let msg = format!(
+                    "Unsupported animation format '{}', expected gif or png",
+                    extension
+                );
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+        };
+        let delay = u16::try_from(delay_ms / 10).unwrap_or(u16::MAX);
+        Ok(Recorder {
+            output,
+            scale,
+            delay,
+        })
+    }
+
+    /// Path of a frame in a PNG sequence, with the frame number appended to
+    /// the file name, such as `frames/out_00042.png` for `frames/out.png`
+    pub fn frame_path(path: &Path, frame: usize) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}_{:05}.png", stem, frame))
+    }
+
+    /// Adds the current frame of the grid
+    pub fn record(&mut self, grid: &Grid) -> io::Result<()> {
+        match &mut self.output {
+            Output::Gif(encoder) => {
+                let (w, h) = grid.get_dims();
+                let (w, h) = ((w * self.scale) as u16, (h * self.scale) as u16);
+                let mut pixels = render_rgba(grid, self.scale);
+                let mut frame =
+                    gif::Frame::from_rgba_speed(w, h, &mut pixels, 10);
+                frame.delay = self.delay;
+                encoder.write_frame(&frame).map_err(gif_error)
+            }
+            Output::Png(path) => {
+                let frame_path = Recorder::frame_path(path, grid.frame());
+                one_shot::write_to_png(grid, &frame_path, self.scale)
+            }
+        }
+    }
+
+    /// Finishes the GIF file. PNG files are complete as soon as they are
+    /// recorded
+    pub fn finish(self) -> io::Result<()> {
+        match self.output {
+            Output::Gif(encoder) => {
+                encoder.into_inner().map_err(gif_error)?;
+                Ok(())
+            }
+            Output::Png(_) => Ok(()),
+        }
+    }
+}
+
+/// Runs the simulation for `n_iterations` more frames, recording the current
+/// frame and then every `every`th frame after it. Panics if `every` is 0
+pub fn main_record(
+    grid: &mut Grid,
+    n_iterations: usize,
+    every: usize,
+    mut recorder: Recorder,
+) -> io::Result<()> {
+    recorder.record(grid)?;
+    for i in 1..=n_iterations {
+        one_shot::run(grid, 1);
+        if i % every == 0 {
+            recorder.record(grid)?;
+        }
+    }
+    recorder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GridBuilder;
+
+    #[test]
+    fn records_every_nth_frame() {
+        let dir = std::env::temp_dir()
+            .join(format!("sable-record-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let grid = || GridBuilder::new(8, 6).build().unwrap();
+
+        let png_path = dir.join("frames.png");
+        let recorder = Recorder::new(&png_path, (8, 6), 1, 0).unwrap();
+        main_record(&mut grid(), 5, 2, recorder).unwrap();
+        let mut names = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            ["frames_00000.png", "frames_00002.png", "frames_00004.png"]
+        );
+
+        let gif_path = dir.join("out.gif");
+        let recorder = Recorder::new(&gif_path, (8, 6), 2, 80).unwrap();
+        main_record(&mut grid(), 6, 3, recorder).unwrap();
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(File::open(&gif_path).unwrap())
+            .unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (16, 12));
+        let mut delays = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays, [8, 8, 8]);
+
+        assert!(Recorder::new(&dir.join("out.mp4"), (8, 6), 1, 0).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
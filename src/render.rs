@@ -0,0 +1,218 @@
+use crate::grid::{Grid, DRAIN_COLOUR, WALL_COLOUR};
+use crate::material::{Cell, Material};
+
+/// What the colour of a cell is derived from when rendering. Every mode
+/// except [`RenderMode::Material`] maps its value through the grid's colour
+/// map. Empty cells are always black, and walls and drains keep their own
+/// colours
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RenderMode {
+    /// The colour value stored in the cell when it was spawned
+    #[default]
+    Colour,
+    /// How recently the cell changed, from 255 for cells that moved in the
+    /// last frame down to 0 for cells that have been still for
+    /// [`MOTION_FADE_FRAMES`] frames or more
+    Motion,
+    /// Number of movable cells stacked below the cell, including itself,
+    /// relative to the height of the grid
+    Height,
+    /// Share of the 3x3 neighbourhood that is filled with movable cells
+    Density,
+    /// A fixed colour for every material
+    Material,
+}
+
+impl RenderMode {
+    // Whether the grid has to keep track of which cells changed between
+    // frames for this mode
+    pub(crate) fn tracks_motion(self) -> bool {
+        self == RenderMode::Motion
+    }
+}
+
+/// Frames it takes a cell that stopped moving to fade out completely in
+/// [`RenderMode::Motion`]
+pub const MOTION_FADE_FRAMES: u8 = 32;
+
+/// Counts, for every position, the frames since the cell there last changed,
+/// saturating at [`MOTION_FADE_FRAMES`]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Motion {
+    frames_still: Vec<u8>,
+}
+
+impl Motion {
+    pub(crate) fn new(size: usize) -> Motion {
+        Motion {
+            frames_still: vec![0; size],
+        }
+    }
+
+    pub(crate) fn update(&mut self, before: &[Cell], after: &[Cell]) {
+        let cells = before.iter().zip(after);
+        for (still, (a, b)) in self.frames_still.iter_mut().zip(cells) {
+            *still = if a == b {
+                still.saturating_add(1).min(MOTION_FADE_FRAMES)
+            } else {
+                0
+            }
+        }
+    }
+
+    pub(crate) fn value(&self, i: usize) -> f64 {
+        let fade = self.frames_still[i] as f64 / MOTION_FADE_FRAMES as f64;
+        u8::MAX as f64 * (1.0 - fade)
+    }
+}
+
+/// Colour of a material in [`RenderMode::Material`]
+pub fn material_colour(material: Material) -> u32 {
+    match material {
+        Material::Empty => 0,
+        Material::Sand => 0xFFC2A060,
+        Material::Water => 0xFF2060D0,
+        Material::Stone => 0xFF687078,
+        Material::Wall => WALL_COLOUR,
+        Material::Drain => DRAIN_COLOUR,
+    }
+}
+
+// Number of movable cells in each contiguous stack, counted from the bottom
+// of the stack up to and including every cell
+fn stack_heights(cells: &[Cell], (w, h): (usize, usize)) -> Vec<usize> {
+    let mut heights = vec![0; cells.len()];
+    for x in 0..w {
+        let mut run = 0;
+        for y in (0..h).rev() {
+            let i = y * w + x;
+            run = if cells[i].material.is_movable() {
+                run + 1
+            } else {
+                0
+            };
+            heights[i] = run
+        }
+    }
+    heights
+}
+
+// Number of movable cells in the 3x3 neighbourhood of (x, y), including the
+// cell itself. Cells outside of the grid count as empty
+fn neighbours(
+    cells: &[Cell],
+    (w, h): (usize, usize),
+    x: usize,
+    y: usize,
+) -> usize {
+    let (x0, x1) = (x.saturating_sub(1), (x + 1).min(w - 1));
+    let (y0, y1) = (y.saturating_sub(1), (y + 1).min(h - 1));
+    (y0..=y1)
+        .flat_map(|y| (x0..=x1).map(move |x| y * w + x))
+        .filter(|&i| cells[i].material.is_movable())
+        .count()
+}
+
+/// Computes the RGBA colour of every cell of the current frame, row by row
+/// from the top, in the grid's render mode
+pub fn render(grid: &Grid) -> Vec<u32> {
+    let cells = grid.get_front();
+    let mode = grid.render_mode();
+    if mode == RenderMode::Colour {
+        return cells.iter().map(|&c| grid.cell_colour(c)).collect();
+    }
+    let dims = grid.get_dims();
+    let (w, h) = dims;
+    let heights = match mode {
+        RenderMode::Height => stack_heights(cells, dims),
+        _ => vec![],
+    };
+    let max = u8::MAX as f64;
+    cells
+        .iter()
+        .enumerate()
+        .map(|(i, &cell)| {
+            if mode == RenderMode::Material {
+                return material_colour(cell.material);
+            }
+            if !cell.material.is_movable() {
+                return grid.cell_colour(cell);
+            }
+            let v = match mode {
+                RenderMode::Motion => grid.motion().value(i),
+                RenderMode::Height => max * heights[i] as f64 / h as f64,
+                RenderMode::Density => {
+                    max * neighbours(cells, dims, i % w, i / w) as f64 / 9.0
+                }
+                RenderMode::Colour | RenderMode::Material => unreachable!(),
+            };
+            grid.convert_colour(v)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colour;
+    use crate::GridBuilder;
+    use std::sync::Arc;
+
+    // Renders in grayscale, so that every value can be read back from the
+    // blue channel
+    fn values(text: &str, mode: RenderMode) -> Vec<u32> {
+        let mut g = text.parse::<Grid>().unwrap();
+        g.set_colour_map(Arc::new(colour::grayscale));
+        g.set_render_mode(mode);
+        g.render().iter().map(|c| c & 0xFF).collect()
+    }
+
+    #[test]
+    fn derives_colours_from_cells() {
+        let text = "
+            0  1  0  0
+            1  1  0 1w
+            1  0# 0  1s
+        ";
+        assert_eq!(
+            values(text, RenderMode::Height),
+            [0, 170, 0, 0, 170, 85, 0, 170, 85, 0x80, 0, 85]
+        );
+        assert_eq!(
+            values(text, RenderMode::Density),
+            [0, 85, 0, 0, 113, 113, 0, 56, 85, 0x80, 0, 56]
+        );
+        let mut g = GridBuilder::new(4, 3)
+            .render_mode(RenderMode::Material)
+            .build()
+            .unwrap();
+        g.set_cells(text.parse::<Grid>().unwrap().get_front());
+        let colours = g.render();
+        assert_eq!(colours[1], material_colour(Material::Sand));
+        assert_eq!(colours[7], material_colour(Material::Water));
+        assert_eq!(colours[9], WALL_COLOUR);
+        assert_eq!(colours[0], 0);
+    }
+
+    #[test]
+    fn fades_out_still_cells() {
+        let mut g = "
+            1  0  0
+            0  0  0
+            0  0  1
+        "
+        .parse::<Grid>()
+        .unwrap();
+        g.set_colour_map(Arc::new(colour::grayscale));
+        g.set_render_mode(RenderMode::Motion);
+        g.next();
+        let frame = g.render();
+        // The grain at the top fell one cell, the one at the bottom didn't
+        assert_eq!(frame[3] & 0xFF, 255);
+        assert_eq!(frame[8] & 0xFF, 255 * 31 / 32);
+        for _ in 0..MOTION_FADE_FRAMES {
+            g.next();
+        }
+        assert_eq!(g.render()[8], 0xFF000000);
+    }
+}
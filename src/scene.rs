@@ -0,0 +1,510 @@
+use crate::colour::parse_hex;
+use crate::grid::{DRAIN_COLOUR, WALL_COLOUR};
+use crate::lines::parse_lines;
+use crate::material::{Cell, Material};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Reasons a scene image can't be turned into cells
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    /// The image file is malformed or uses an unsupported encoding
+    Decode(String),
+    /// Only `.png` and `.bmp` files are supported
+    UnsupportedFormat(String),
+    DimensionMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    /// A pixel has a colour that isn't in the palette
+    UnknownColour {
+        colour: u32,
+        x: usize,
+        y: usize,
+    },
+}
+
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(fmt, "{}", e),
+            SceneError::Decode(e) => write!(fmt, "Invalid image: {}", e),
+            SceneError::UnsupportedFormat(extension) => write!(
+                fmt,
+                "Unsupported image format '{}', expected png or bmp",
+                extension
+            ),
+            SceneError::DimensionMismatch { expected, found } => write!(
+                fmt,
+                "Expected a {}x{} image, got {}x{}",
+                expected.0, expected.1, found.0, found.1
+            ),
+            SceneError::UnknownColour { colour, x, y } => write!(
+                fmt,
+                "Colour {:06x} at ({}, {}) isn't in the palette",
+                colour, x, y
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> SceneError {
+        SceneError::Io(e)
+    }
+}
+
+/// Maps the RGB colours of a scene image, as `0xRRGGBB`, to cells
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    entries: Vec<(u32, Cell)>,
+}
+
+impl Palette {
+    pub fn new() -> Palette {
+        Palette { entries: vec![] }
+    }
+
+    /// Replaces any cell the colour was already mapped to
+    pub fn insert(mut self, rgb: u32, cell: Cell) -> Palette {
+        self.entries.retain(|&(c, _)| c != rgb);
+        self.entries.push((rgb, cell));
+        self
+    }
+
+    pub fn get(&self, rgb: u32) -> Option<Cell> {
+        self.entries
+            .iter()
+            .find(|&&(c, _)| c == rgb)
+            .map(|&(_, cell)| cell)
+    }
+}
+
+/// Black is empty, and walls and drains use the colours they are rendered
+/// with
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::new()
+            .insert(0x000000, Cell::EMPTY)
+            .insert(WALL_COLOUR & 0xFFFFFF, Cell::new(Material::Wall, 0))
+            .insert(DRAIN_COLOUR & 0xFFFFFF, Cell::new(Material::Drain, 0))
+    }
+}
+
+// Parses one colour per line, as a hex RGB value followed by a material and
+// an optional colour value, such as `c2b280 sand 40`. The colour value
+// defaults to 1 for materials that move, as 0 stands for an empty cell in
+// text scenes. Colours that aren't listed keep their default mapping. Blank
+// lines and lines starting with `#` are ignored
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_line = |line: &str| -> Result<(u32, Cell), String> {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let (rgb, material, colour) = match words[..] {
+                [rgb, material] => (rgb, material, None),
+                [rgb, material, colour] => (rgb, material, Some(colour)),
+                _ => {
+                    return Err(String::from(
+                        "Expected '<rrggbb> <material> [<colour>]'",
+                    ))
+                }
+            };
+            let rgb = parse_hex(rgb)?;
+            let material: Material = material.parse()?;
+            let colour = match colour {
+                Some(colour) => colour.parse().map_err(|e| {
+                    format!("Invalid colour '{}': {}", colour, e)
+                })?,
+                None => material.is_movable() as u8,
+            };
+            if colour == 0 && material.is_movable() {
+                return Err(String::from(
+                    "Colour 0 is reserved for empty cells",
+                ));
+            }
+            Ok((rgb, Cell::new(material, colour)))
+        };
+        parse_lines(s, parse_line).try_fold(
+            Palette::default(),
+            |palette, entry| {
+                let (rgb, cell) = entry?;
+                Ok(palette.insert(rgb, cell))
+            },
+        )
+    }
+}
+
+/// Pixels of an image as `0xRRGGBB` values, row by row from the top. Alpha
+/// is ignored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u32>,
+}
+
+fn rgb(r: u8, g: u8, b: u8) -> u32 {
+    u32::from_be_bytes([0, r, g, b])
+}
+
+impl Image {
+    /// Panics if the number of pixels doesn't match the dimensions
+    pub fn new(width: usize, height: usize, pixels: Vec<u32>) -> Image {
+        assert_eq!(pixels.len(), width * height, "Wrong number of pixels");
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Reads a PNG or BMP file, depending on its extension
+    pub fn open(path: &Path) -> Result<Image, SceneError> {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "png" => Image::read_png(path),
+            "bmp" => Image::read_bmp(path),
+            _ => Err(SceneError::UnsupportedFormat(extension)),
+        }
+    }
+
+    fn read_png(path: &Path) -> Result<Image, SceneError> {
+        let decode = |e: png::DecodingError| SceneError::Decode(e.to_string());
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        // Palettes and bit depths other than 8 are converted to 8 bit
+        // grayscale or RGB, with or without alpha
+        decoder
+            .set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(decode)?;
+        let size = reader.output_buffer_size().ok_or_else(|| {
+            SceneError::Decode(String::from("Image is too large"))
+        })?;
+        let mut buf = vec![0; size];
+        let info = reader.next_frame(&mut buf).map_err(decode)?;
+        let samples = info.color_type.samples();
+        let pixels = buf[..info.buffer_size()]
+            .chunks(samples)
+            .map(|p| match samples {
+                1 | 2 => rgb(p[0], p[0], p[0]),
+                _ => rgb(p[0], p[1], p[2]),
+            })
+            .collect();
+        let (width, height) = (info.width as usize, info.height as usize);
+        Ok(Image::new(width, height, pixels))
+    }
+
+    fn read_bmp(path: &Path) -> Result<Image, SceneError> {
+        Image::decode_bmp(&std::fs::read(path)?).map_err(SceneError::Decode)
+    }
+
+    // Decodes 24-bit and 32-bit BMP files, either uncompressed, as image
+    // editors and `one_shot::write_bmp` write them, or with channel masks
+    fn decode_bmp(data: &[u8]) -> Result<Image, String> {
+        let truncated = || String::from("File is truncated");
+        let bytes = |at: usize, len: usize| {
+            at.checked_add(len)
+                .and_then(|end| data.get(at..end))
+                .ok_or_else(truncated)
+        };
+        let u16_at =
+            |at| bytes(at, 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        let u32_at = |at| {
+            bytes(at, 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        if bytes(0, 2)? != b"BM" {
+            return Err(String::from("Not a BMP file"));
+        }
+        let offset = u32_at(10)? as usize;
+        // Later versions of BITMAPINFOHEADER only add fields after it
+        let header_len = u32_at(14)?;
+        if header_len < 40 {
+            return Err(format!("Unsupported header length {}", header_len));
+        }
+        let width = u32_at(18)? as i32;
+        let height = u32_at(22)? as i32;
+        if width <= 0 || height == 0 {
+            return Err(format!("Invalid dimensions {}x{}", width, height));
+        }
+        let bit_count = u16_at(28)?;
+        let compression = u32_at(30)?;
+        // Red, green and blue masks of the pixels read as little endian
+        // integers. They follow BITMAPINFOHEADER, or are part of the
+        // versions after it
+        let masks = match (bit_count, compression) {
+            (24 | 32, 0) => [0xFF0000, 0xFF00, 0xFF],
+            (32, 3) => [u32_at(54)?, u32_at(58)?, u32_at(62)?],
+            _ => {
+                return Err(format!(
+                    "Unsupported {}-bit encoding {}",
+                    bit_count, compression
+                ))
+            }
+        };
+        let channel = |pixel: u32, mask: u32| {
+            let bits = mask.count_ones();
+            let value = (pixel & mask).checked_shr(mask.trailing_zeros());
+            let value = value.unwrap_or(0) as u64;
+            let value = match bits {
+                0 => 0,
+                1..=8 => value * 255 / ((1 << bits) - 1),
+                _ => value >> (bits - 8),
+            };
+            value.min(255) as u8
+        };
+        let (width, height, top_down) =
+            (width as usize, height.unsigned_abs() as usize, height < 0);
+        let pixel_len = bit_count as usize / 8;
+        // Rows are padded to a multiple of 4 bytes
+        let too_large = || String::from("Image is too large");
+        let row_len = width
+            .checked_mul(pixel_len)
+            .and_then(|len| len.checked_next_multiple_of(4))
+            .ok_or_else(too_large)?;
+        let pixel_data =
+            bytes(offset, row_len.checked_mul(height).ok_or_else(too_large)?)?;
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            // Rows are stored bottom up, unless the height is negative
+            let row = if top_down { y } else { height - 1 - y };
+            let row = &pixel_data[row * row_len..][..width * pixel_len];
+            for pixel in row.chunks_exact(pixel_len) {
+                let mut le_bytes = [0; 4];
+                le_bytes[..pixel_len].copy_from_slice(pixel);
+                let pixel = u32::from_le_bytes(le_bytes);
+                let [r, g, b] = masks.map(|mask| channel(pixel, mask));
+                pixels.push(rgb(r, g, b))
+            }
+        }
+        Ok(Image::new(width, height, pixels))
+    }
+
+    /// Width and height of the image, in pixels
+    pub fn get_dims(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Stretches the image with nearest neighbour sampling, so that blocks
+    /// of colour stay exactly the same colour. An empty image has nothing to
+    /// sample, and is returned as is
+    pub fn resize(&self, width: usize, height: usize) -> Image {
+        if self.pixels.is_empty() {
+            return self.clone();
+        }
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let src_y = y * self.height / height;
+            for x in 0..width {
+                let src_x = x * self.width / width;
+                pixels.push(self.pixels[src_y * self.width + src_x])
+            }
+        }
+        Image::new(width, height, pixels)
+    }
+
+    /// Maps every pixel to a cell with the palette. The image must have the
+    /// expected dimensions, see [`Image::resize`]
+    pub fn to_cells(
+        &self,
+        palette: &Palette,
+        expected: (usize, usize),
+    ) -> Result<Vec<Cell>, SceneError> {
+        let found = self.get_dims();
+        if found != expected {
+            return Err(SceneError::DimensionMismatch { expected, found });
+        }
+        self.pixels
+            .iter()
+            .enumerate()
+            .map(|(i, &colour)| {
+                palette.get(colour).ok_or(SceneError::UnknownColour {
+                    colour,
+                    x: i % self.width,
+                    y: i / self.width,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{one_shot, Grid};
+    use std::sync::Arc;
+
+    #[test]
+    fn parses_palettes() {
+        let palette: Palette = "# Sand in two shades\n\
+                                c2b280 sand 40\n\
+                                \n\
+                                a08a5a   sand 41\n\
+                                0000ff water\n\
+                                000000 stone 3\n"
+            .parse()
+            .unwrap();
+        assert_eq!(palette.get(0xc2b280), Some(Cell::new(Material::Sand, 40)));
+        assert_eq!(palette.get(0x0000ff), Some(Cell::new(Material::Water, 1)));
+        // Defaults can be overridden
+        assert_eq!(palette.get(0x000000), Some(Cell::new(Material::Stone, 3)));
+        assert_eq!(palette.get(0x808080), Some(Cell::new(Material::Wall, 0)));
+        assert_eq!(palette.get(0xffffff), None);
+        let err = |s: &str| s.parse::<Palette>().err().unwrap();
+        assert_eq!(err("fff sand"), "Line 1: Invalid hex colour 'fff'");
+        assert_eq!(err("\nffffff lava"), "Line 2: Unknown material 'lava'");
+        assert!(err("ffffff sand 256").starts_with("Line 1: Invalid colour"));
+        assert!(err("ffffff").starts_with("Line 1: Expected"));
+        assert_eq!(
+            err("ffffff sand 0"),
+            "Line 1: Colour 0 is reserved for empty cells"
+        );
+        let palette: Palette = "ffffff wall".parse().unwrap();
+        assert_eq!(palette.get(0xffffff), Some(Cell::new(Material::Wall, 0)));
+    }
+
+    #[test]
+    fn maps_pixels_to_cells() {
+        let palette =
+            Palette::default().insert(0xff0000, Cell::new(Material::Sand, 9));
+        let image = Image::new(2, 2, vec![0xff0000, 0, 0x808080, 0xff0000]);
+        let cells = image.to_cells(&palette, (2, 2)).unwrap();
+        let sand = Cell::new(Material::Sand, 9);
+        let wall = Cell::new(Material::Wall, 0);
+        assert_eq!(cells, vec![sand, Cell::EMPTY, wall, sand]);
+        assert!(matches!(
+            image.to_cells(&palette, (4, 2)),
+            Err(SceneError::DimensionMismatch { .. })
+        ));
+        let resized = image.resize(4, 2).to_cells(&palette, (4, 2)).unwrap();
+        assert_eq!(
+            resized,
+            vec![sand, sand, Cell::EMPTY, Cell::EMPTY, wall, wall, sand, sand]
+        );
+        let unknown = Image::new(2, 1, vec![0, 0x123456]);
+        assert!(matches!(
+            unknown.to_cells(&palette, (2, 1)),
+            Err(SceneError::UnknownColour {
+                colour: 0x123456,
+                x: 1,
+                y: 0
+            })
+        ));
+    }
+
+    // A BMP file with a BITMAPINFOHEADER, followed by `extra` and the pixel
+    // data
+    fn bmp_file(
+        (width, height): (i32, i32),
+        bit_count: u16,
+        compression: u32,
+        extra: &[u8],
+        pixel_data: &[u8],
+    ) -> Vec<u8> {
+        let offset = 54 + extra.len() as u32;
+        let mut data = b"BM".to_vec();
+        data.extend((offset + pixel_data.len() as u32).to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(offset.to_le_bytes());
+        data.extend(40u32.to_le_bytes());
+        data.extend(width.to_le_bytes());
+        data.extend(height.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(bit_count.to_le_bytes());
+        data.extend(compression.to_le_bytes());
+        data.extend([0; 20]);
+        data.extend(extra);
+        data.extend(pixel_data);
+        data
+    }
+
+    #[test]
+    fn decodes_bmp_files() {
+        let expected = Image::new(2, 2, vec![0xff0000, 0x00ff00, 0x0000ff, 0]);
+        // 24-bit rows of 6 bytes, padded to 8, from the bottom
+        let rows = [255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 0, 255, 0, 0, 0];
+        let file = bmp_file((2, 2), 24, 0, &[], &rows);
+        assert_eq!(Image::decode_bmp(&file), Ok(expected.clone()));
+        // From the top
+        let rows = [0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0];
+        let file = bmp_file((2, -2), 24, 0, &[], &rows);
+        assert_eq!(Image::decode_bmp(&file), Ok(expected.clone()));
+        // 32-bit with masks for R, G and B in that order, and 5 bits of blue
+        let masks = [0xFFu32, 0xFF00, 0x1F0000]
+            .iter()
+            .flat_map(|mask| mask.to_le_bytes())
+            .collect::<Vec<_>>();
+        let rows = [0, 0, 0x1F, 0, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0, 0];
+        let file = bmp_file((2, 2), 32, 3, &masks, &rows);
+        assert_eq!(Image::decode_bmp(&file), Ok(expected));
+
+        let err = |file: &[u8]| Image::decode_bmp(file).unwrap_err();
+        let file = bmp_file((2, 2), 24, 0, &[], &rows[..15]);
+        assert_eq!(err(&file), "File is truncated");
+        assert_eq!(err(&file[..20]), "File is truncated");
+        assert_eq!(err(b"GIF89a"), "Not a BMP file");
+        let file = bmp_file((0, 2), 24, 0, &[], &[]);
+        assert_eq!(err(&file), "Invalid dimensions 0x2");
+        let file = bmp_file((2, 2), 8, 0, &[], &rows);
+        assert_eq!(err(&file), "Unsupported 8-bit encoding 0");
+        assert_eq!(Image::new(0, 0, vec![]).resize(2, 2).get_dims(), (0, 0));
+    }
+
+    #[test]
+    fn reads_images() {
+        let dir = std::env::temp_dir()
+            .join(format!("sable-scene-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let expected = Image::new(3, 2, vec![0xff0000, 0, 0, 0, 0, 0x0000ff]);
+
+        // 32-bit, as written by sable itself
+        let bmp_path = dir.join("scene.bmp");
+        let mut grid = "1 0 0\n0 0 2".parse::<Grid>().unwrap();
+        grid.set_colour_map(Arc::new(|v: f64| match v as u8 {
+            1 => 0xFFFF0000,
+            _ => 0xFF0000FF,
+        }));
+        one_shot::write_to_bmp(&grid, &bmp_path).unwrap();
+        assert_eq!(Image::open(&bmp_path).unwrap(), expected);
+
+        let png_path = dir.join("scene.PNG");
+        let file = File::create(&png_path).unwrap();
+        let mut encoder = png::Encoder::new(file, 3, 2);
+        encoder.set_color(png::ColorType::Rgb);
+        let mut writer = encoder.write_header().unwrap();
+        let mut data = vec![0; 18];
+        data[0] = 255;
+        data[17] = 255;
+        writer.write_image_data(&data).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(Image::open(&png_path).unwrap(), expected);
+
+        assert!(matches!(
+            Image::open(&dir.join("scene.gif")),
+            Err(SceneError::UnsupportedFormat(_))
+        ));
+        assert!(matches!(
+            Image::open(&dir.join("missing.png")),
+            Err(SceneError::Io(_))
+        ));
+        let bad_path = dir.join("bad.bmp");
+        std::fs::write(&bad_path, b"BM\0\0\0").unwrap();
+        assert!(matches!(Image::open(&bad_path), Err(SceneError::Decode(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Outline used to draw cells onto a grid. Coordinates are inclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Point {
+        x: usize,
+        y: usize,
+    },
+    Rect {
+        x0: usize,
+        y0: usize,
+        x1: usize,
+        y1: usize,
+    },
+    Line {
+        x0: usize,
+        y0: usize,
+        x1: usize,
+        y1: usize,
+    },
+    Circle {
+        x: usize,
+        y: usize,
+        r: usize,
+    },
+}
+
+impl Shape {
+    /// Computes the coordinates of every point covered by the shape that
+    /// lies inside a grid of the given dimensions. Only the part of the
+    /// shape overlapping the grid is visited, so shapes can be arbitrarily
+    /// large
+    pub fn points_within(
+        &self,
+        (width, height): (usize, usize),
+    ) -> Vec<(usize, usize)> {
+        if width == 0 || height == 0 {
+            return vec![];
+        }
+        let (max_x, max_y) = (width - 1, height - 1);
+        match *self {
+            Shape::Point { x, y } if x <= max_x && y <= max_y => vec![(x, y)],
+            Shape::Point { .. } => vec![],
+            Shape::Rect { x0, y0, x1, y1 } => {
+                let (x0, x1) = (x0.min(x1), x0.max(x1).min(max_x));
+                let (y0, y1) = (y0.min(y1), y0.max(y1).min(max_y));
+                (y0..=y1)
+                    .flat_map(|y| (x0..=x1).map(move |x| (x, y)))
+                    .collect()
+            }
+            Shape::Line { x0, y0, x1, y1 } => {
+                line_points((x0, y0), (x1, y1), (width, height))
+            }
+            Shape::Circle { x, y, r } => {
+                let y0 = y.saturating_sub(r);
+                let y1 = y.saturating_add(r).min(max_y);
+                let r2 = r as u128 * r as u128;
+                (y0..=y1)
+                    .flat_map(|py| {
+                        // Half the width of the row, which fits in a usize
+                        // as it is at most r
+                        let dy = py.abs_diff(y) as u128;
+                        let half = (r2 - dy * dy).isqrt() as usize;
+                        let x0 = x.saturating_sub(half);
+                        let x1 = x.saturating_add(half).min(max_x);
+                        (x0..=x1).map(move |px| (px, py))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+// Moves `d` steps from `a` towards `b`
+fn step(a: usize, b: usize, d: usize) -> usize {
+    if a <= b {
+        a + d
+    } else {
+        a - d
+    }
+}
+
+// Steps from `a` towards `b` that land on a coordinate below `len`, if
+// there are any
+fn steps_inside(
+    a: usize,
+    b: usize,
+    len: usize,
+) -> Option<RangeInclusive<usize>> {
+    match a <= b {
+        true if a >= len => None,
+        true => Some(0..=b.min(len - 1) - a),
+        false if b >= len => None,
+        false => Some(a.saturating_sub(len - 1)..=a - b),
+    }
+}
+
+// Points of the line with the same result as Bresenham's line algorithm,
+// computed directly for each step along the longer axis so that steps
+// outside of the grid can be skipped. The minor coordinate is the exact
+// offset rounded half up
+fn line_points(
+    (x0, y0): (usize, usize),
+    (x1, y1): (usize, usize),
+    (width, height): (usize, usize),
+) -> Vec<(usize, usize)> {
+    let (dx, dy) = (x0.abs_diff(x1), y0.abs_diff(y1));
+    let (major, minor) = (dx.max(dy) as u128, dx.min(dy) as u128);
+    let offset = |t: usize| {
+        if major == 0 {
+            return 0;
+        }
+        let (q, rem) = (t as u128 * minor / major, t as u128 * minor % major);
+        (q + (2 * rem >= major) as u128) as usize
+    };
+    let points = if dx >= dy {
+        steps_inside(x0, x1, width)
+            .into_iter()
+            .flatten()
+            .map(|t| (step(x0, x1, t), step(y0, y1, offset(t))))
+            .collect::<Vec<_>>()
+    } else {
+        steps_inside(y0, y1, height)
+            .into_iter()
+            .flatten()
+            .map(|t| (step(x0, x1, offset(t)), step(y0, y1, t)))
+            .collect()
+    };
+    points
+        .into_iter()
+        .filter(|&(x, y)| x < width && y < height)
+        .collect()
+}
+
+// Writes the shape in the format parsed by `Shape::from_str`
+impl std::fmt::Display for Shape {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shape::Point { x, y } => write!(fmt, "point:{},{}", x, y),
+            Shape::Rect { x0, y0, x1, y1 } => {
+                write!(fmt, "rect:{},{},{},{}", x0, y0, x1, y1)
+            }
+            Shape::Line { x0, y0, x1, y1 } => {
+                write!(fmt, "line:{},{},{},{}", x0, y0, x1, y1)
+            }
+            Shape::Circle { x, y, r } => {
+                write!(fmt, "circle:{},{},{}", x, y, r)
+            }
+        }
+    }
+}
+
+// Parses shapes written as `point:x,y`, `rect:x0,y0,x1,y1`,
+// `line:x0,y0,x1,y1`, or `circle:x,y,r`
+impl FromStr for Shape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, args) = s
+            .split_once(':')
+            .ok_or(format!("Expected '<shape>:<args>', got '{}'", s))?;
+        let args = args
+            .split(',')
+            .map(|a| a.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid argument in '{}': {}", s, e))?;
+        match (kind, args.as_slice()) {
+            ("point", &[x, y]) => Ok(Shape::Point { x, y }),
+            ("rect", &[x0, y0, x1, y1]) => Ok(Shape::Rect { x0, y0, x1, y1 }),
+            ("line", &[x0, y0, x1, y1]) => Ok(Shape::Line { x0, y0, x1, y1 }),
+            ("circle", &[x, y, r]) => {
+                if x.checked_add(r).is_none() || y.checked_add(r).is_none() {
+                    return Err(format!("Radius {} is too large", r));
+                }
+                Ok(Shape::Circle { x, y, r })
+            }
+            ("rect" | "line", _) => {
+                Err(format!("'{}' expects 4 arguments: x0,y0,x1,y1", kind))
+            }
+            ("point", _) => {
+                Err(String::from("'point' expects 2 arguments: x,y"))
+            }
+            ("circle", _) => {
+                Err(String::from("'circle' expects 3 arguments: x,y,r"))
+            }
+            _ => Err(format!("Unknown shape '{}'", kind)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_shapes() {
+        assert_eq!(
+            "rect:1,2,3,4".parse(),
+            Ok(Shape::Rect {
+                x0: 1,
+                y0: 2,
+                x1: 3,
+                y1: 4
+            })
+        );
+        assert_eq!(
+            "line:0,0,5,5".parse(),
+            Ok(Shape::Line {
+                x0: 0,
+                y0: 0,
+                x1: 5,
+                y1: 5
+            })
+        );
+        assert_eq!(
+            "circle:3,3,2".parse(),
+            Ok(Shape::Circle { x: 3, y: 3, r: 2 })
+        );
+        assert_eq!("point:7,0".parse(), Ok(Shape::Point { x: 7, y: 0 }));
+        assert!("circle:3,3".parse::<Shape>().is_err());
+        assert!("square:1,1,2,2".parse::<Shape>().is_err());
+        assert!("rect:a,1,2,2".parse::<Shape>().is_err());
+        for s in ["point:7,0", "rect:1,2,3,4", "line:0,0,5,5", "circle:3,3,2"] {
+            assert_eq!(s.parse::<Shape>().unwrap().to_string(), s);
+        }
+        assert_eq!(
+            "circle:5,0,18446744073709551615".parse::<Shape>(),
+            Err(String::from("Radius 18446744073709551615 is too large"))
+        );
+    }
+
+    #[test]
+    fn line_includes_endpoints() {
+        let points = Shape::Line {
+            x0: 4,
+            y0: 2,
+            x1: 0,
+            y1: 0,
+        }
+        .points_within((10, 10));
+        assert_eq!(points.first(), Some(&(4, 2)));
+        assert_eq!(points.last(), Some(&(0, 0)));
+        assert_eq!(points.len(), 5);
+    }
+
+    #[test]
+    fn clips_lines_to_the_grid() {
+        let line = |x0, y0, x1, y1| Shape::Line { x0, y0, x1, y1 };
+        // Clipping only drops the points outside of the grid
+        let full = line(0, 0, 19, 7).points_within((20, 20));
+        let clipped = full
+            .iter()
+            .copied()
+            .filter(|&(x, y)| (3..12).contains(&x) && y < 5)
+            .collect::<Vec<_>>();
+        let shifted = line(0, 0, 19, 7)
+            .points_within((12, 5))
+            .into_iter()
+            .filter(|&(x, _)| x >= 3)
+            .collect::<Vec<_>>();
+        assert_eq!(shifted, clipped);
+        let reversed = line(19, 7, 0, 0).points_within((12, 5));
+        assert_eq!(reversed.first(), Some(&(11, 4)));
+        assert_eq!(reversed.last(), Some(&(0, 0)));
+        assert!(line(20, 0, 30, 2).points_within((10, 10)).is_empty());
+        assert!(line(0, 20, 2, 30).points_within((10, 10)).is_empty());
+    }
+
+    #[test]
+    fn clips_huge_shapes() {
+        let dims = (10, 10);
+        let rect = Shape::Rect {
+            x0: 0,
+            y0: 0,
+            x1: 30000,
+            y1: 30000,
+        };
+        assert_eq!(rect.points_within(dims).len(), 100);
+        let circle = Shape::Circle {
+            x: 5,
+            y: 5,
+            r: 5_000_000_000,
+        };
+        assert_eq!(circle.points_within(dims).len(), 100);
+        let max = usize::MAX;
+        let circle = Shape::Circle { x: 5, y: 0, r: max };
+        assert_eq!(circle.points_within(dims).len(), 100);
+        let line = Shape::Line {
+            x0: max,
+            y0: max,
+            x1: 0,
+            y1: 0,
+        };
+        assert_eq!(line.points_within(dims).len(), 10);
+        let far = Shape::Point { x: max, y: 0 };
+        assert!(far.points_within(dims).is_empty());
+        let circle = Shape::Circle { x: 5, y: 5, r: 2 };
+        assert_eq!(circle.points_within(dims).len(), 13);
+        assert_eq!(circle.points_within((5, 5)), [(4, 4)]);
+    }
+}
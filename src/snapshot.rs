@@ -0,0 +1,292 @@
+use crate::material::{Cell, Material};
+use std::io::{self, Read, Write};
+
+// Identifies snapshot files, followed by the format version
+const MAGIC: &[u8; 4] = b"SABL";
+const VERSION: u32 = 1;
+
+/// Reasons a snapshot can't be read or restored
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The data doesn't start with the snapshot header
+    NotASnapshot,
+    UnsupportedVersion(u32),
+    InvalidMaterial(u8),
+    /// The number of cells doesn't fit in memory
+    TooLarge {
+        width: u64,
+        height: u64,
+    },
+    /// The snapshot was taken from a grid of different dimensions
+    DimensionMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(fmt, "{}", e),
+            SnapshotError::NotASnapshot => write!(fmt, "Not a sable snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(fmt, "Unsupported snapshot version {}", version)
+            }
+            SnapshotError::InvalidMaterial(material) => {
+                write!(fmt, "Invalid material {} in snapshot", material)
+            }
+            SnapshotError::TooLarge { width, height } => {
+                write!(fmt, "A {}x{} snapshot is too large", width, height)
+            }
+            SnapshotError::DimensionMismatch { expected, found } => write!(
+                fmt,
+                "Expected a {}x{} snapshot, got {}x{}",
+                expected.0, expected.1, found.0, found.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> SnapshotError {
+        SnapshotError::Io(e)
+    }
+}
+
+/// The complete state of a grid at the start of a frame, as written by
+/// [`Grid::save`](crate::Grid::save). Restoring it with
+/// [`Grid::restore`](crate::Grid::restore) continues the simulation exactly
+/// where it left off
+///
+/// All integers are stored little-endian, after a header of
+/// `SABL`, the format version, the dimensions, the seed, the state of the
+/// random number generator and the frame counter. Cells follow row by row
+/// from the top as a material and a colour byte each, and then one byte per
+/// chunk telling whether it changed in the previous frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) seed: u64,
+    pub(crate) rng_key: [u8; 32],
+    pub(crate) rng_stream: u64,
+    pub(crate) rng_word_pos: u128,
+    pub(crate) frame: usize,
+    pub(crate) cells: Vec<Cell>,
+    pub(crate) changed_chunks: Vec<bool>,
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    read_array(r).map(u64::from_le_bytes)
+}
+
+// Reads exactly `len` bytes, without trusting `len` enough to allocate it
+// all up front
+fn read_vec(r: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    r.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+impl Snapshot {
+    /// Width and height of the grid the snapshot was taken from
+    pub fn get_dims(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Seed the grid was created with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Number of frames the grid had been advanced by
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn write(&self, mut w: impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        for n in [self.width, self.height] {
+            w.write_all(&(n as u64).to_le_bytes())?;
+        }
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(&self.rng_key)?;
+        w.write_all(&self.rng_stream.to_le_bytes())?;
+        w.write_all(&self.rng_word_pos.to_le_bytes())?;
+        w.write_all(&(self.frame as u64).to_le_bytes())?;
+        let cells = self
+            .cells
+            .iter()
+            .flat_map(|c| [c.material as u8, c.colour])
+            .collect::<Vec<_>>();
+        w.write_all(&cells)?;
+        w.write_all(&(self.changed_chunks.len() as u64).to_le_bytes())?;
+        let changed = self.changed_chunks.iter().map(|&c| c as u8);
+        w.write_all(&changed.collect::<Vec<_>>())?;
+        w.flush()
+    }
+
+    pub fn read(mut r: impl Read) -> Result<Snapshot, SnapshotError> {
+        if &read_array(&mut r)? != MAGIC {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let version = u32::from_le_bytes(read_array(&mut r)?);
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let (width, height) = (read_u64(&mut r)?, read_u64(&mut r)?);
+        let too_large = SnapshotError::TooLarge { width, height };
+        let n_bytes = width
+            .checked_mul(height)
+            .and_then(|size| size.checked_mul(2))
+            .and_then(|bytes| usize::try_from(bytes).ok())
+            .filter(|&bytes| bytes <= isize::MAX as usize)
+            .ok_or(too_large)?;
+        let seed = read_u64(&mut r)?;
+        let rng_key = read_array(&mut r)?;
+        let rng_stream = read_u64(&mut r)?;
+        let rng_word_pos = u128::from_le_bytes(read_array(&mut r)?);
+        let frame = read_u64(&mut r)? as usize;
+        let cells = read_vec(&mut r, n_bytes)?
+            .chunks(2)
+            .map(|pair| Ok(Cell::new(Material::try_from(pair[0])?, pair[1])))
+            .collect::<Result<Vec<_>, u8>>()
+            .map_err(SnapshotError::InvalidMaterial)?;
+        let n_chunks = read_u64(&mut r)?;
+        let n_chunks = usize::try_from(n_chunks).unwrap_or(usize::MAX);
+        let changed_chunks = read_vec(&mut r, n_chunks)?
+            .into_iter()
+            .map(|c| c != 0)
+            .collect();
+        Ok(Snapshot {
+            width: width as usize,
+            height: height as usize,
+            seed,
+            rng_key,
+            rng_stream,
+            rng_word_pos,
+            frame,
+            cells,
+            changed_chunks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{one_shot, Grid, GridBuilder, Scheduler, Shape, Spawner};
+
+    fn scene(scheduler: Scheduler, n_threads: usize, seed: u64) -> Grid {
+        let spawner = Spawner::new(Shape::Point { x: 3, y: 0 });
+        let mut g = GridBuilder::new(100, 40)
+            .threads(n_threads)
+            .seed(seed)
+            .scheduler(scheduler)
+            .spawner(spawner.material(Material::Water))
+            .build()
+            .unwrap();
+        g.draw_wall(&Shape::Line {
+            x0: 50,
+            y0: 20,
+            x1: 50,
+            y1: 39,
+        });
+        // Settles into a pile that sleeps, to the right of water that
+        // doesn't. Waking it up would change the random choices made by the
+        // water in the same rows
+        let pile = Shape::Rect {
+            x0: 92,
+            y0: 36,
+            x1: 99,
+            y1: 39,
+        };
+        g.draw(&pile, Cell::new(Material::Sand, 7));
+        g
+    }
+
+    fn saved(g: &Grid) -> Vec<u8> {
+        let mut bytes = vec![];
+        g.save(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn resumes_exactly() {
+        for scheduler in [Scheduler::Ribbons, Scheduler::Margolus] {
+            let mut expected = scene(scheduler, 2, 5);
+            one_shot::run(&mut expected, 80);
+            let bytes = saved(&expected);
+            one_shot::run(&mut expected, 80);
+
+            // Neither the seed nor the thread count of the new grid matter
+            let mut g = scene(scheduler, 3, 6);
+            g.load(bytes.as_slice()).unwrap();
+            assert_eq!((g.seed(), g.frame()), (5, 80));
+            one_shot::run(&mut g, 80);
+            assert_eq!(g.frame(), 160);
+            assert!(g.get_front() == expected.get_front());
+            assert_eq!(saved(&g), saved(&expected));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_snapshots() {
+        let g = scene(Scheduler::Ribbons, 1, 0);
+        let bytes = saved(&g);
+        let load = |bytes: &[u8]| scene(Scheduler::Ribbons, 1, 0).load(bytes);
+        assert!(matches!(load(b"BMP"), Err(SnapshotError::Io(_))));
+        assert!(matches!(
+            load(b"not a snapshot"),
+            Err(SnapshotError::NotASnapshot)
+        ));
+        let mut version = bytes.clone();
+        version[4] = 9;
+        assert!(matches!(
+            load(&version),
+            Err(SnapshotError::UnsupportedVersion(9))
+        ));
+        assert!(matches!(
+            load(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Io(_))
+        ));
+        // The first cell follows a 96 byte header
+        let mut material = bytes.clone();
+        material[96] = 42;
+        assert!(matches!(
+            load(&material),
+            Err(SnapshotError::InvalidMaterial(42))
+        ));
+        let mut huge = bytes.clone();
+        huge[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(load(&huge), Err(SnapshotError::TooLarge { .. })));
+        let mut small = GridBuilder::new(10, 10).build().unwrap();
+        assert!(matches!(
+            small.load(bytes.as_slice()),
+            Err(SnapshotError::DimensionMismatch {
+                expected: (10, 10),
+                found: (100, 40)
+            })
+        ));
+    }
+}
@@ -7,27 +7,21 @@ use winit::event::{Event, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{Window, WindowBuilder};
 
-use crate::grid::Grid;
+use sable::Grid;
 
 fn get_buf_pixel(
-    grid: &Grid,
     x: u32,
     y: u32,
     screen_width: u32,
     screen_height: u32,
-    buf: &[u8],
+    buf: &[u32],
     (buf_width, buf_height): (usize, usize),
 ) -> u32 {
     let xf = x as f64 / screen_width as f64;
     let yf = y as f64 / screen_height as f64;
     let xb = (xf * buf_width as f64) as usize;
     let yb = (yf * buf_height as f64) as usize;
-    let v = buf[yb * buf_width + xb];
-    if v == 0 {
-        0
-    } else {
-        grid.convert_colour(v as f64)
-    }
+    buf[yb * buf_width + xb]
 }
 
 fn handle_redraw_request(
@@ -47,18 +41,12 @@ fn handle_redraw_request(
         )
         .unwrap();
     let mut buffer = surface.buffer_mut().unwrap();
+    let colours = grid.render();
     for i in 0..(width * height) {
         let y = i / width;
         let x = i % width;
-        buffer[i as usize] = get_buf_pixel(
-            grid,
-            x,
-            y,
-            width,
-            height,
-            grid.get_front(),
-            grid.get_dims(),
-        );
+        buffer[i as usize] =
+            get_buf_pixel(x, y, width, height, &colours, grid.get_dims());
     }
     buffer.present().unwrap();
     grid.spawn(frame);
@@ -0,0 +1,222 @@
+use crate::lines::parse_lines;
+use crate::material::{Cell, Material};
+use crate::shape::Shape;
+use rand::RngCore;
+use std::str::FromStr;
+
+/// How the colour of spawned cells is picked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColourSchedule {
+    Constant(u8),
+    /// Steps through every colour, moving on every `period` frames
+    Cycle {
+        period: u32,
+    },
+    Random,
+}
+
+impl ColourSchedule {
+    fn colour(self, frame: u32, rng: &mut impl RngCore) -> u8 {
+        // Colour 0 is skipped, as it is used for empty cells in the text
+        // representation of sand grids
+        match self {
+            ColourSchedule::Constant(colour) => colour,
+            ColourSchedule::Cycle { period } => {
+                ((frame / period) % 254 + 1) as u8
+            }
+            ColourSchedule::Random => (rng.next_u32() % 254 + 1) as u8,
+        }
+    }
+}
+
+// Parses a colour value, `cycle`, `cycle:<period>`, or `random`. Colour 0
+// is rejected, as spawned cells would read back as empty
+impl FromStr for ColourSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, arg) = s.split_once(':').unwrap_or((s, ""));
+        match (kind, arg) {
+            ("random", "") => Ok(ColourSchedule::Random),
+            ("cycle", "") => Ok(ColourSchedule::Cycle { period: 5 }),
+            ("cycle", period) => match period.parse() {
+                Ok(period) if period > 0 => {
+                    Ok(ColourSchedule::Cycle { period })
+                }
+                _ => Err(format!("Invalid cycle period '{}'", period)),
+            },
+            _ => match s.parse() {
+                Ok(0) => {
+                    Err(String::from("Colour 0 is reserved for empty cells"))
+                }
+                Ok(colour) => Ok(ColourSchedule::Constant(colour)),
+                Err(_) => Err(format!(
+                    "Expected a colour, 'cycle[:<period>]' or 'random', \
+                     got '{}'",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Drops cells at random points of a shape every frame. Points that are
+/// already occupied are left alone
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Spawner {
+    pub shape: Shape,
+    /// Number of points picked per frame
+    pub rate: usize,
+    pub material: Material,
+    pub colour: ColourSchedule,
+}
+
+impl Spawner {
+    /// Drops one sand grain per frame, cycling through the colours
+    pub fn new(shape: Shape) -> Spawner {
+        Spawner {
+            shape,
+            rate: 1,
+            material: Material::Sand,
+            colour: ColourSchedule::Cycle { period: 5 },
+        }
+    }
+
+    /// Drops sand along the whole top row, one grain for every 20 columns
+    pub fn top_row(width: usize) -> Spawner {
+        Spawner {
+            rate: width / 20 + 1,
+            ..Spawner::new(Shape::Line {
+                x0: 0,
+                y0: 0,
+                x1: width.saturating_sub(1),
+                y1: 0,
+            })
+        }
+    }
+
+    pub fn rate(self, rate: usize) -> Spawner {
+        Spawner { rate, ..self }
+    }
+
+    pub fn material(self, material: Material) -> Spawner {
+        Spawner { material, ..self }
+    }
+
+    pub fn colour(self, colour: ColourSchedule) -> Spawner {
+        Spawner { colour, ..self }
+    }
+
+    pub(crate) fn cell(&self, frame: u32, rng: &mut impl RngCore) -> Cell {
+        Cell::new(self.material, self.colour.colour(frame, rng))
+    }
+}
+
+// Parses a shape followed by optional space separated settings, such as
+// `point:50,0 rate=2 material=water colour=random`. Only materials that
+// move can be spawned
+impl FromStr for Spawner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let shape = words.next().ok_or("Expected a shape")?.parse()?;
+        let mut spawner = Spawner::new(shape);
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or(format!("Expected '<key>=<value>', got '{}'", word))?;
+            match key {
+                "rate" => {
+                    spawner.rate = value.parse().map_err(|e| {
+                        format!("Invalid rate '{}': {}", value, e)
+                    })?
+                }
+                "material" => {
+                    spawner.material = value.parse()?;
+                    if !spawner.material.is_movable() {
+                        return Err(format!("Can't spawn '{}'", value));
+                    }
+                }
+                "colour" => spawner.colour = value.parse()?,
+                _ => return Err(format!("Unknown spawner setting '{}'", key)),
+            }
+        }
+        Ok(spawner)
+    }
+}
+
+/// Reads one spawner per line, in the same format as [`Spawner::from_str`].
+/// Blank lines and lines starting with `#` are ignored
+pub fn parse_spawners(s: &str) -> Result<Vec<Spawner>, String> {
+    parse_lines(s, str::parse).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_spawners() {
+        assert_eq!(
+            "point:3,0".parse(),
+            Ok(Spawner::new(Shape::Point { x: 3, y: 0 }))
+        );
+        assert_eq!(
+            "rect:0,0,9,1 rate=4 material=water colour=random".parse(),
+            Ok(Spawner::new(Shape::Rect {
+                x0: 0,
+                y0: 0,
+                x1: 9,
+                y1: 1
+            })
+            .rate(4)
+            .material(Material::Water)
+            .colour(ColourSchedule::Random))
+        );
+        assert_eq!(
+            "point:1,1 colour=cycle:2"
+                .parse::<Spawner>()
+                .unwrap()
+                .colour,
+            ColourSchedule::Cycle { period: 2 }
+        );
+        assert_eq!(
+            "point:1,1 colour=7".parse::<Spawner>().unwrap().colour,
+            ColourSchedule::Constant(7)
+        );
+        assert!("point:1,1 colour=cycle:0".parse::<Spawner>().is_err());
+        assert!("point:1,1 material=lava".parse::<Spawner>().is_err());
+        assert_eq!(
+            "point:1,1 colour=00".parse::<Spawner>(),
+            Err(String::from("Colour 0 is reserved for empty cells"))
+        );
+        for material in ["empty", "wall", "drain"] {
+            assert_eq!(
+                format!("point:1,1 material={}", material).parse::<Spawner>(),
+                Err(format!("Can't spawn '{}'", material))
+            );
+        }
+        assert!("point:1,1 speed=2".parse::<Spawner>().is_err());
+        assert!("".parse::<Spawner>().is_err());
+    }
+
+    #[test]
+    fn parses_spawner_files() {
+        let spawners = parse_spawners(
+            "# Two hoppers\n\
+             point:10,0 rate=1\n\
+             \n\
+             point:30,0 material=stone\n",
+        )
+        .unwrap();
+        assert_eq!(spawners.len(), 2);
+        assert_eq!(spawners[1].material, Material::Stone);
+        assert_eq!(
+            parse_spawners("point:1,1\nline:0,0"),
+            Err(String::from(
+                "Line 2: 'line' expects 4 arguments: x0,y0,x1,y1"
+            ))
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use crate::grid::Grid;
+use crate::one_shot::{self, render_rgba};
+use std::io::{self, Write};
+
+/// Encoding of frames written by [`main_stream`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StreamFormat {
+    /// Raw RGBA bytes, row by row from the top, with nothing between frames
+    #[default]
+    Rgba,
+    /// YUV4MPEG2 with 4:4:4 chroma, which carries its own dimensions and
+    /// frame rate
+    Y4m,
+}
+
+// Converts RGBA to studio range BT.601 YCbCr, as expected by Y4M readers
+fn rgb_to_ycbcr([r, g, b, _]: [u8; 4]) -> [u8; 3] {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let cb = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let cr = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    [y as u8, cb as u8, cr as u8]
+}
+
+// Writes the Y, Cb and Cr planes of a frame one after the other
+fn write_y4m_frame(out: &mut impl Write, rgba: &[u8]) -> io::Result<()> {
+    let pixels = rgba
+        .chunks_exact(4)
+        .map(|p| rgb_to_ycbcr([p[0], p[1], p[2], p[3]]))
+        .collect::<Vec<_>>();
+    out.write_all(b"FRAME\n")?;
+    for plane in 0..3 {
+        out.write_all(&pixels.iter().map(|p| p[plane]).collect::<Vec<_>>())?;
+    }
+    Ok(())
+}
+
+/// Runs the simulation for `n_iterations` more frames, writing every frame
+/// to `out` after it is computed. Every cell is drawn as a `scale` by
+/// `scale` square. The frame rate is only stored in Y4M streams
+pub fn main_stream(
+    grid: &mut Grid,
+    n_iterations: usize,
+    format: StreamFormat,
+    fps: u32,
+    scale: usize,
+    mut out: impl Write,
+) -> io::Result<()> {
+    if format == StreamFormat::Y4m {
+        let (w, h) = grid.get_dims();
+        let (w, h) = (w * scale, h * scale);
+        writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", w, h, fps)?;
+    }
+    for _ in 0..n_iterations {
+        one_shot::run(grid, 1);
+        let rgba = render_rgba(grid, scale);
+        match format {
+            StreamFormat::Rgba => out.write_all(&rgba)?,
+            StreamFormat::Y4m => write_y4m_frame(&mut out, &rgba)?,
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GridBuilder;
+
+    #[test]
+    fn converts_to_studio_range() {
+        assert_eq!(rgb_to_ycbcr([0, 0, 0, 255]), [16, 128, 128]);
+        assert_eq!(rgb_to_ycbcr([255, 255, 255, 255]), [235, 128, 128]);
+        assert_eq!(rgb_to_ycbcr([255, 0, 0, 255]), [82, 90, 240]);
+    }
+
+    #[test]
+    fn streams_every_frame() {
+        let grid = || GridBuilder::new(4, 3).build().unwrap();
+        let mut rgba = vec![];
+        main_stream(&mut grid(), 5, StreamFormat::Rgba, 30, 2, &mut rgba)
+            .unwrap();
+        assert_eq!(rgba.len(), 5 * (8 * 6 * 4));
+
+        let mut y4m = vec![];
+        main_stream(&mut grid(), 2, StreamFormat::Y4m, 25, 1, &mut y4m)
+            .unwrap();
+        let header = b"YUV4MPEG2 W4 H3 F25:1 Ip A1:1 C444\n";
+        assert!(y4m.starts_with(header));
+        let frame_len = b"FRAME\n".len() + 3 * 4 * 3;
+        assert_eq!(y4m.len(), header.len() + 2 * frame_len);
+        assert!(y4m[header.len() + frame_len..].starts_with(b"FRAME\n"));
+    }
+}